@@ -0,0 +1,108 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::{Body as AxumBody, Bytes},
+    http::Request as HttpRequest,
+    routing::{get, put},
+    Router, Server,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+const SIZE: usize = 4 * 1024 * 1024;
+
+fn backend(rt: &Runtime) -> SocketAddr {
+    let listen_addr = SocketAddr::from((
+        [127, 0, 0, 1],
+        portpicker::pick_unused_port().expect("No ports free"),
+    ));
+
+    let payload = Bytes::from(vec![b'x'; SIZE]);
+    let app = Router::new()
+        .route("/download", get(move || async move { payload }))
+        .route(
+            "/upload",
+            put(|request: HttpRequest<AxumBody>| async move {
+                let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                body.len().to_string()
+            }),
+        );
+    rt.spawn(async move {
+        let server = Server::bind(&listen_addr).serve(app.into_make_service());
+
+        server.await.expect("backend start failed");
+    });
+    rt.block_on(async { tokio::time::sleep(tokio::time::Duration::from_millis(200)).await });
+
+    listen_addr
+}
+
+fn request(method: &str, uri: &str, body: AxumBody) -> HttpRequest<AxumBody> {
+    HttpRequest::builder()
+        .method(method)
+        .uri(uri)
+        .body(body)
+        .unwrap()
+}
+
+fn bench_send(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let listen_addr = backend(&rt);
+    let download_uri = format!("http://{listen_addr}/download");
+    let upload_uri = format!("http://{listen_addr}/upload");
+    let payload = Bytes::from(vec![b'x'; SIZE]);
+
+    let reqwest_client = reqwest::Client::new();
+    let isahc_client = isahc::HttpClient::new().unwrap();
+
+    //
+    let mut group = c.benchmark_group("download");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function(BenchmarkId::new("reqwest", SIZE), |b| {
+        b.to_async(&rt).iter(|| async {
+            let request = request("GET", &download_uri, AxumBody::empty());
+            let response = axum_request_send::impl_reqwest::send(&reqwest_client, request)
+                .await
+                .unwrap();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body.len(), SIZE);
+        })
+    });
+    group.bench_function(BenchmarkId::new("isahc", SIZE), |b| {
+        b.to_async(&rt).iter(|| async {
+            let request = request("GET", &download_uri, AxumBody::empty());
+            let response = axum_request_send::impl_isahc::send(&isahc_client, request)
+                .await
+                .unwrap();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body.len(), SIZE);
+        })
+    });
+    group.finish();
+
+    //
+    let mut group = c.benchmark_group("upload");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function(BenchmarkId::new("reqwest", SIZE), |b| {
+        b.to_async(&rt).iter(|| async {
+            let request = request("PUT", &upload_uri, AxumBody::from(payload.to_owned()));
+            let response = axum_request_send::impl_reqwest::send(&reqwest_client, request)
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+        })
+    });
+    group.bench_function(BenchmarkId::new("isahc", SIZE), |b| {
+        b.to_async(&rt).iter(|| async {
+            let request = request("PUT", &upload_uri, AxumBody::from(payload.to_owned()));
+            let response = axum_request_send::impl_isahc::send(&isahc_client, request)
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_send);
+criterion_main!(benches);
@@ -0,0 +1,949 @@
+use core::time::Duration;
+use std::{
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{Body as AxumBody, HttpBody as _},
+    http::{
+        header::{InvalidHeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, HOST},
+        request::Parts as HttpRequestParts,
+        HeaderMap, HeaderName, HeaderValue, Method, Request as HttpRequest, Uri,
+    },
+    response::Response as AxumResponse,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use hmac::{Hmac, Mac as _};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest as _, Sha256};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::{
+    error::SendError,
+    retry::buffer_body,
+    sender::{BoxFuture, BoxRequestSender, RequestSender},
+    Config,
+};
+
+//
+/// Adds credentials to a request, see `Auth`.
+pub trait RequestSigner: Send + Sync {
+    fn sign<'a>(
+        &'a self,
+        http_request: &'a mut HttpRequest<AxumBody>,
+    ) -> BoxFuture<'a, Result<(), SendError>>;
+}
+
+impl<T> RequestSigner for Arc<T>
+where
+    T: RequestSigner + ?Sized,
+{
+    fn sign<'a>(
+        &'a self,
+        http_request: &'a mut HttpRequest<AxumBody>,
+    ) -> BoxFuture<'a, Result<(), SendError>> {
+        self.as_ref().sign(http_request)
+    }
+}
+
+//
+/// Signs each request with `signer` just before it is sent by `inner`.
+///
+/// Wrap the backend client directly, so that retries and other decorators get a fresh signature.
+#[derive(Clone)]
+pub struct Auth<S> {
+    inner: S,
+    signer: Arc<dyn RequestSigner>,
+}
+
+impl<S> core::fmt::Debug for Auth<S>
+where
+    S: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Auth")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Auth<S> {
+    pub fn new(inner: S, signer: impl RequestSigner + 'static) -> Self {
+        Self {
+            inner,
+            signer: Arc::new(signer),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> RequestSender for Auth<S>
+where
+    S: RequestSender,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        mut http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            self.signer.sign(&mut http_request).await?;
+            self.inner
+                .send_with_config(http_request, config)
+                .await
+                .map_err(Into::into)
+        })
+    }
+}
+
+//
+/// `Authorization: Bearer <token>`.
+#[derive(Debug, Clone)]
+pub struct BearerToken(HeaderValue);
+
+impl BearerToken {
+    pub fn new(token: impl AsRef<str>) -> Result<Self, InvalidHeaderValue> {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token.as_ref()))?;
+        value.set_sensitive(true);
+        Ok(Self(value))
+    }
+}
+
+impl RequestSigner for BearerToken {
+    fn sign<'a>(
+        &'a self,
+        http_request: &'a mut HttpRequest<AxumBody>,
+    ) -> BoxFuture<'a, Result<(), SendError>> {
+        http_request
+            .headers_mut()
+            .insert(AUTHORIZATION, self.0.to_owned());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+//
+/// `Authorization: Basic <base64(username:password)>`.
+#[derive(Debug, Clone)]
+pub struct BasicAuth(HeaderValue);
+
+impl BasicAuth {
+    pub fn new(username: impl AsRef<str>, password: impl AsRef<str>) -> Self {
+        Self(basic_auth(username.as_ref(), password.as_ref()))
+    }
+}
+
+impl RequestSigner for BasicAuth {
+    fn sign<'a>(
+        &'a self,
+        http_request: &'a mut HttpRequest<AxumBody>,
+    ) -> BoxFuture<'a, Result<(), SendError>> {
+        http_request
+            .headers_mut()
+            .insert(AUTHORIZATION, self.0.to_owned());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+fn basic_auth(username: &str, password: &str) -> HeaderValue {
+    let credentials = BASE64.encode(format!("{username}:{password}"));
+    let mut value = HeaderValue::from_str(&format!("Basic {credentials}"))
+        .expect("base64 is a valid header value");
+    value.set_sensitive(true);
+    value
+}
+
+//
+/// OAuth 2.0 client credentials grant (RFC 6749 section 4.4), as a bearer token.
+///
+/// The token is cached and fetched again `refresh_before` its `expires_in`,
+/// concurrent requests wait for the same fetch.
+/// The client authenticates to `token_uri` with HTTP Basic.
+pub struct OAuth2ClientCredentials {
+    client: BoxRequestSender,
+    token_uri: Uri,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    refresh_before: Duration,
+    token: AsyncMutex<Option<(HeaderValue, Option<Instant>)>>,
+}
+
+impl core::fmt::Debug for OAuth2ClientCredentials {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OAuth2ClientCredentials")
+            .field("token_uri", &self.token_uri)
+            .field("client_id", &self.client_id)
+            .field("scopes", &self.scopes)
+            .field("refresh_before", &self.refresh_before)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OAuth2ClientCredentials {
+    /// `client` sends the token requests.
+    pub fn new<C>(
+        client: C,
+        token_uri: Uri,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self
+    where
+        C: RequestSender + 'static,
+        C::Error: Into<SendError>,
+    {
+        Self {
+            client: BoxRequestSender::new(client),
+            token_uri,
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scopes: vec![],
+            refresh_before: Duration::from_secs(30),
+            token: Default::default(),
+        }
+    }
+
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.push(scope.into());
+        self
+    }
+
+    pub fn refresh_before(mut self, refresh_before: Duration) -> Self {
+        self.refresh_before = refresh_before;
+        self
+    }
+
+    /// Forgets the cached token, e.g. after the upstream rejected it.
+    pub async fn invalidate(&self) {
+        *self.token.lock().await = None;
+    }
+
+    async fn token(&self) -> Result<HeaderValue, SendError> {
+        let mut token = self.token.lock().await;
+        if let Some((value, expires_at)) = token.as_ref() {
+            let valid = expires_at
+                .map(|x| Instant::now() + self.refresh_before < x)
+                .unwrap_or(true);
+            if valid {
+                return Ok(value.to_owned());
+            }
+        }
+
+        let fetched = self.fetch_token().await?;
+        let value = fetched.0.to_owned();
+        *token = Some(fetched);
+        Ok(value)
+    }
+
+    async fn fetch_token(&self) -> Result<(HeaderValue, Option<Instant>), SendError> {
+        const MAX_BODY_SIZE: usize = 64 * 1024;
+
+        let form = {
+            let mut form = form_urlencoded::Serializer::new(String::new());
+            form.append_pair("grant_type", "client_credentials");
+            if !self.scopes.is_empty() {
+                form.append_pair("scope", &self.scopes.join(" "));
+            }
+            form.finish()
+        };
+        // Ref https://www.rfc-editor.org/rfc/rfc6749#section-2.3.1
+        let encode = |x: &str| form_urlencoded::byte_serialize(x.as_bytes()).collect::<String>();
+        let http_request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(self.token_uri.to_owned())
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(ACCEPT, "application/json")
+            .header(
+                AUTHORIZATION,
+                basic_auth(&encode(&self.client_id), &encode(&self.client_secret)),
+            )
+            .body(AxumBody::from(form))?;
+
+        let requested_at = Instant::now();
+        let response = self.client.send(http_request).await?;
+        let status = response.status();
+        let mut body = response.into_body();
+        let mut bytes = vec![];
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.map_err(|err| SendError::BodyStream(err.into()))?);
+            if bytes.len() > MAX_BODY_SIZE {
+                return Err(SendError::Protocol("token response is too large".into()));
+            }
+        }
+        if !status.is_success() {
+            return Err(SendError::Other(
+                format!("token endpoint responded {status}").into(),
+            ));
+        }
+
+        let json = serde_json::from_slice::<serde_json::Value>(&bytes)
+            .map_err(|err| SendError::Protocol(err.into()))?;
+        let access_token = json
+            .get("access_token")
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| SendError::Protocol("token response has no access_token".into()))?;
+        if let Some(token_type) = json.get("token_type").and_then(|x| x.as_str()) {
+            if !token_type.eq_ignore_ascii_case("bearer") {
+                return Err(SendError::Protocol(
+                    format!("unsupported token_type {token_type}").into(),
+                ));
+            }
+        }
+        let expires_at = json
+            .get("expires_in")
+            .and_then(|x| x.as_u64())
+            .map(|x| requested_at + Duration::from_secs(x));
+
+        let mut value = HeaderValue::from_str(&format!("Bearer {access_token}"))
+            .map_err(|err| SendError::Protocol(err.into()))?;
+        value.set_sensitive(true);
+        Ok((value, expires_at))
+    }
+}
+
+impl RequestSigner for OAuth2ClientCredentials {
+    fn sign<'a>(
+        &'a self,
+        http_request: &'a mut HttpRequest<AxumBody>,
+    ) -> BoxFuture<'a, Result<(), SendError>> {
+        Box::pin(async move {
+            let token = self.token().await?;
+            http_request.headers_mut().insert(AUTHORIZATION, token);
+            Ok(())
+        })
+    }
+}
+
+//
+/// HMAC-SHA256 request signature.
+///
+/// The signed string is `method \n path_and_query \n timestamp \n hex(sha256(body))`,
+/// `timestamp` is the unix time in seconds, sent in `timestamp_header` (default `x-timestamp`).
+/// `signature_header` (default `x-signature`) is
+/// `keyId="<key_id>",algorithm="hmac-sha256",signature="<hex>"`.
+///
+/// The body is buffered to be hashed, a body larger than `max_body_size` fails the request.
+#[derive(Clone)]
+pub struct HmacSignature {
+    key_id: String,
+    secret: Vec<u8>,
+    signature_header: HeaderName,
+    timestamp_header: HeaderName,
+    max_body_size: usize,
+}
+
+impl core::fmt::Debug for HmacSignature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("HmacSignature")
+            .field("key_id", &self.key_id)
+            .field("signature_header", &self.signature_header)
+            .field("timestamp_header", &self.timestamp_header)
+            .field("max_body_size", &self.max_body_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HmacSignature {
+    pub fn new(key_id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key_id: key_id.into(),
+            secret: secret.into(),
+            signature_header: HeaderName::from_static("x-signature"),
+            timestamp_header: HeaderName::from_static("x-timestamp"),
+            max_body_size: 1024 * 1024,
+        }
+    }
+
+    pub fn signature_header(mut self, name: HeaderName) -> Self {
+        self.signature_header = name;
+        self
+    }
+
+    pub fn timestamp_header(mut self, name: HeaderName) -> Self {
+        self.timestamp_header = name;
+        self
+    }
+
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    fn sign_parts(
+        &self,
+        parts: &mut HttpRequestParts,
+        body_hash: &str,
+        now: SystemTime,
+    ) -> Result<(), SendError> {
+        let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let path_and_query = parts
+            .uri
+            .path_and_query()
+            .map(|x| x.as_str())
+            .unwrap_or("/");
+        let string_to_sign = format!(
+            "{}\n{path_and_query}\n{timestamp}\n{body_hash}",
+            parts.method
+        );
+        let signature = hex::encode(hmac_sha256(&self.secret, string_to_sign.as_bytes()));
+
+        parts.headers.insert(
+            self.timestamp_header.to_owned(),
+            HeaderValue::from(timestamp),
+        );
+        parts.headers.insert(
+            self.signature_header.to_owned(),
+            HeaderValue::from_str(&format!(
+                r#"keyId="{}",algorithm="hmac-sha256",signature="{signature}""#,
+                self.key_id
+            ))?,
+        );
+        Ok(())
+    }
+}
+
+impl RequestSigner for HmacSignature {
+    fn sign<'a>(
+        &'a self,
+        http_request: &'a mut HttpRequest<AxumBody>,
+    ) -> BoxFuture<'a, Result<(), SendError>> {
+        Box::pin(async move {
+            let body = core::mem::take(http_request.body_mut());
+            let body = buffer_body(http_request.headers(), body, self.max_body_size)
+                .await?
+                .map_err(|_| {
+                    SendError::PayloadTooLarge("request body is too large to sign".into())
+                })?;
+
+            let (mut parts, _) = core::mem::take(http_request).into_parts();
+            self.sign_parts(
+                &mut parts,
+                &hex::encode(Sha256::digest(&body)),
+                SystemTime::now(),
+            )?;
+            *http_request = HttpRequest::from_parts(parts, AxumBody::from(body));
+            Ok(())
+        })
+    }
+}
+
+//
+/// AWS Signature Version 4, in the `Authorization` header.
+///
+/// Signs `host`, `content-type`, `x-amz-*` and the payload hash. For S3, a body larger than
+/// `max_body_size` is not buffered and is sent with `UNSIGNED-PAYLOAD`,
+/// other services do not accept that and fail the request with `SendError::PayloadTooLarge`.
+#[derive(Clone)]
+pub struct AwsSigV4 {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    region: String,
+    service: String,
+    max_body_size: usize,
+}
+
+impl core::fmt::Debug for AwsSigV4 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AwsSigV4")
+            .field("access_key_id", &self.access_key_id)
+            .field("region", &self.region)
+            .field("service", &self.service)
+            .field("max_body_size", &self.max_body_size)
+            .finish_non_exhaustive()
+    }
+}
+
+const X_AMZ_DATE: HeaderName = HeaderName::from_static("x-amz-date");
+const X_AMZ_SECURITY_TOKEN: HeaderName = HeaderName::from_static("x-amz-security-token");
+const X_AMZ_CONTENT_SHA256: HeaderName = HeaderName::from_static("x-amz-content-sha256");
+
+// Ref https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html
+const AWS_URI_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+impl AwsSigV4 {
+    pub fn new(
+        access_key_id: impl Into<String>,
+        secret_access_key: impl Into<String>,
+        region: impl Into<String>,
+        service: impl Into<String>,
+    ) -> Self {
+        Self {
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+            session_token: None,
+            region: region.into(),
+            service: service.into(),
+            max_body_size: 1024 * 1024,
+        }
+    }
+
+    /// For temporary credentials, sent in `x-amz-security-token`.
+    pub fn session_token(mut self, session_token: impl Into<String>) -> Self {
+        self.session_token = Some(session_token.into());
+        self
+    }
+
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    fn sign_parts(
+        &self,
+        parts: &mut HttpRequestParts,
+        payload_hash: &str,
+        now: SystemTime,
+    ) -> Result<(), SendError> {
+        let (date, amz_date) = amz_date(now);
+        let headers = &mut parts.headers;
+        if !headers.contains_key(HOST) {
+            let authority = parts
+                .uri
+                .authority()
+                .ok_or_else(|| SendError::InvalidUri("no host to sign".into()))?;
+            headers.insert(HOST, HeaderValue::from_str(authority.as_str())?);
+        }
+        headers.insert(X_AMZ_DATE, HeaderValue::from_str(&amz_date)?);
+        if let Some(session_token) = &self.session_token {
+            headers.insert(X_AMZ_SECURITY_TOKEN, HeaderValue::from_str(session_token)?);
+        }
+        if self.service == "s3" {
+            headers.insert(X_AMZ_CONTENT_SHA256, HeaderValue::from_str(payload_hash)?);
+        }
+
+        //
+        let (canonical_headers, signed_headers) = canonical_headers(headers);
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            parts.method,
+            canonical_uri(parts.uri.path(), self.service != "s3"),
+            canonical_query(parts.uri.query().unwrap_or_default()),
+        );
+
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = [
+            date.as_str(),
+            self.region.as_str(),
+            self.service.as_str(),
+            "aws4_request",
+        ]
+        .iter()
+        .fold(
+            format!("AWS4{}", self.secret_access_key).into_bytes(),
+            |key, x| hmac_sha256(&key, x.as_bytes()),
+        );
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        let mut value = HeaderValue::from_str(&format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key_id
+        ))?;
+        value.set_sensitive(true);
+        parts.headers.insert(AUTHORIZATION, value);
+        Ok(())
+    }
+}
+
+impl RequestSigner for AwsSigV4 {
+    fn sign<'a>(
+        &'a self,
+        http_request: &'a mut HttpRequest<AxumBody>,
+    ) -> BoxFuture<'a, Result<(), SendError>> {
+        Box::pin(async move {
+            let (mut parts, body) = core::mem::take(http_request).into_parts();
+            let (payload_hash, body) =
+                match buffer_body(&parts.headers, body, self.max_body_size).await? {
+                    Ok(body) => (hex::encode(Sha256::digest(&body)), AxumBody::from(body)),
+                    Err(body) if self.service == "s3" => ("UNSIGNED-PAYLOAD".to_owned(), body),
+                    Err(_) => {
+                        return Err(SendError::PayloadTooLarge(
+                            "request body is too large to sign".into(),
+                        ))
+                    }
+                };
+            self.sign_parts(&mut parts, &payload_hash, SystemTime::now())?;
+            *http_request = HttpRequest::from_parts(parts, body);
+            Ok(())
+        })
+    }
+}
+
+/// The canonical headers and the signed header names.
+fn canonical_headers(headers: &HeaderMap) -> (String, String) {
+    let mut signed = headers
+        .iter()
+        .filter(|(name, _)| {
+            *name == HOST || *name == CONTENT_TYPE || name.as_str().starts_with("x-amz-")
+        })
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            (name.as_str(), value)
+        })
+        .collect::<Vec<_>>();
+    // By name only, the values of a repeated header keep their order.
+    signed.sort_by_key(|(name, _)| *name);
+    let mut canonical_headers = String::new();
+    let mut names = vec![];
+    for (i, (name, value)) in signed.iter().enumerate() {
+        if i > 0 && signed[i - 1].0 == *name {
+            canonical_headers.pop();
+            canonical_headers.push_str(&format!(",{value}\n"));
+        } else {
+            canonical_headers.push_str(&format!("{name}:{value}\n"));
+            names.push(*name);
+        }
+    }
+    (canonical_headers, names.join(";"))
+}
+
+/// Each segment is normalized to a single encoding, and encoded once more for non-S3 services.
+fn canonical_uri(path: &str, double_encode: bool) -> String {
+    if path.is_empty() {
+        return "/".to_owned();
+    }
+    path.split('/')
+        .map(|segment| {
+            let decoded = percent_decode_str(segment).decode_utf8_lossy();
+            let encoded = utf8_percent_encode(&decoded, AWS_URI_ENCODE).to_string();
+            if double_encode {
+                utf8_percent_encode(&encoded, AWS_URI_ENCODE).to_string()
+            } else {
+                encoded
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn canonical_query(query: &str) -> String {
+    let encode = |x: &str| {
+        let decoded = percent_decode_str(x).decode_utf8_lossy();
+        utf8_percent_encode(&decoded, AWS_URI_ENCODE).to_string()
+    };
+    let mut pairs = query
+        .split('&')
+        .filter(|x| !x.is_empty())
+        .map(|x| match x.split_once('=') {
+            Some((k, v)) => (encode(k), encode(v)),
+            None => (encode(x), String::new()),
+        })
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs
+        .into_iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// `(YYYYMMDD, YYYYMMDDTHHMMSSZ)` in UTC.
+fn amz_date(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // Ref https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{year:04}{month:02}{day:02}");
+    let amz_date = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    );
+    (date, amz_date)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes a key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{
+        extract::State,
+        http::StatusCode,
+        response::IntoResponse as _,
+        routing::{any, post},
+        Router,
+    };
+
+    use crate::impl_service::ServiceSender;
+
+    #[test]
+    fn test_aws_sig_v4() {
+        // Ref https://github.com/awslabs/aws-c-auth/tree/main/tests/aws-signing-test-suite/v4/get-vanilla
+        let signer = AwsSigV4::new(
+            "AKIDEXAMPLE",
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "us-east-1",
+            "service",
+        );
+        let (mut parts, _) = HttpRequest::builder()
+            .uri("http://example.amazonaws.com/")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let now = UNIX_EPOCH + Duration::from_secs(1440938160);
+        signer
+            .sign_parts(&mut parts, &hex::encode(Sha256::digest(b"")), now)
+            .unwrap();
+        assert_eq!(parts.headers.get(X_AMZ_DATE).unwrap(), "20150830T123600Z");
+        assert_eq!(
+            parts.headers.get(AUTHORIZATION).unwrap(),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("example.com"));
+        headers.append("x-amz-meta-a", HeaderValue::from_static("b"));
+        headers.append("x-amz-meta-a", HeaderValue::from_static("a  z"));
+        headers.insert("x-other", HeaderValue::from_static("x"));
+        assert_eq!(
+            canonical_headers(&headers),
+            (
+                "host:example.com\nx-amz-meta-a:b,a z\n".to_owned(),
+                "host;x-amz-meta-a".to_owned()
+            )
+        );
+
+        assert_eq!(canonical_uri("/a b/%7Ex", false), "/a%20b/~x");
+        assert_eq!(canonical_uri("/a b", true), "/a%2520b");
+        assert_eq!(canonical_query("b=2&a=x y&a=1&c"), "a=1&a=x%20y&b=2&c=");
+        assert_eq!(amz_date(UNIX_EPOCH).1, "19700101T000000Z");
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(951782400)).0,
+            "20000229"
+        );
+    }
+
+    #[test]
+    fn test_hmac_signature() {
+        let (mut parts, _) = HttpRequest::builder()
+            .method(Method::POST)
+            .uri("http://example.com/hmac?x=1")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let now = UNIX_EPOCH + Duration::from_secs(1700000000);
+        HmacSignature::new("key", "secret")
+            .sign_parts(&mut parts, &hex::encode(Sha256::digest(b"foo")), now)
+            .unwrap();
+        assert_eq!(parts.headers.get("x-timestamp").unwrap(), "1700000000");
+        assert_eq!(
+            parts.headers.get("x-signature").unwrap(),
+            r#"keyId="key",algorithm="hmac-sha256",signature="0dff3e6afa32fa6bb3c1fffb4ef38904fb325ddb49d8fa9df91cafdeb509fe02""#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        let backend: Router = Router::new().route(
+            "/*path",
+            any(|request: HttpRequest<AxumBody>| async move {
+                let (parts, body) = request.into_parts();
+                let body = hyper::body::to_bytes(body).await.unwrap();
+                let header = |name: &str| {
+                    parts
+                        .headers
+                        .get(name)
+                        .map(|x| x.to_str().unwrap().to_owned())
+                        .unwrap_or_default()
+                };
+                if parts.uri.path() == "/hmac" {
+                    let string_to_sign = format!(
+                        "POST\n/hmac\n{}\n{}",
+                        header("x-timestamp"),
+                        // sha256("foo")
+                        "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+                    );
+                    let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+                    mac.update(string_to_sign.as_bytes());
+                    let signature = header("x-signature");
+                    let signature = signature
+                        .strip_prefix(r#"keyId="key",algorithm="hmac-sha256",signature=""#)
+                        .and_then(|x| x.strip_suffix('"'))
+                        .unwrap();
+                    mac.verify_slice(&hex::decode(signature).unwrap()).unwrap();
+                    return String::from_utf8(body.to_vec()).unwrap();
+                }
+                header("authorization")
+            }),
+        );
+
+        let request = |uri: &str| {
+            HttpRequest::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .body(AxumBody::from("foo"))
+                .unwrap()
+        };
+
+        let sender = Auth::new(
+            ServiceSender::new(backend.to_owned()),
+            BearerToken::new("t")?,
+        );
+        let response = sender.send(request("/bearer")).await?;
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "Bearer t"
+        );
+
+        let sender = Auth::new(
+            ServiceSender::new(backend.to_owned()),
+            BasicAuth::new("Aladdin", "open sesame"),
+        );
+        let response = sender.send(request("/basic")).await?;
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+
+        let sender = Auth::new(
+            ServiceSender::new(backend.to_owned()),
+            HmacSignature::new("key", "secret"),
+        );
+        let response = sender.send(request("/hmac")).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "foo");
+
+        let sender = Auth::new(
+            ServiceSender::new(backend.to_owned()),
+            HmacSignature::new("key", "secret").max_body_size(2),
+        );
+        let err = sender.send(request("/hmac")).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Only S3 accepts an unsigned payload.
+        let sender = Auth::new(
+            ServiceSender::new(backend.to_owned()),
+            AwsSigV4::new("key", "secret", "us-east-1", "s3").max_body_size(2),
+        );
+        let response = sender
+            .send(request("http://bucket.s3.amazonaws.com/object"))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let sender = Auth::new(
+            ServiceSender::new(backend),
+            AwsSigV4::new("key", "secret", "us-east-1", "sqs").max_body_size(2),
+        );
+        let err = sender
+            .send(request("http://sqs.us-east-1.amazonaws.com/queue"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_client_credentials() -> Result<(), Box<dyn std::error::Error>> {
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let token_endpoint: Router = Router::new()
+            .route(
+                "/token",
+                post(
+                    |State(fetches): State<Arc<AtomicUsize>>,
+                     request: HttpRequest<AxumBody>| async move {
+                        let (parts, body) = request.into_parts();
+                        let body = hyper::body::to_bytes(body).await.unwrap();
+                        assert_eq!(
+                            parts.headers.get(AUTHORIZATION).unwrap(),
+                            // `client id` form-urlencoded before base64.
+                            basic_auth("client+id", "secret")
+                        );
+                        let form = form_urlencoded::parse(&body).collect::<Vec<_>>();
+                        if !form.iter().any(|(k, v)| k == "scope" && v == "read write") {
+                            return StatusCode::BAD_REQUEST.into_response();
+                        }
+                        let n = fetches.fetch_add(1, Ordering::SeqCst) + 1;
+                        (
+                            [(CONTENT_TYPE, "application/json")],
+                            format!(
+                                r#"{{"access_token":"token{n}","token_type":"bearer","expires_in":{}}}"#,
+                                if n == 1 { 3600 } else { 10 }
+                            ),
+                        )
+                            .into_response()
+                    },
+                ),
+            )
+            .with_state(fetches.to_owned());
+        let backend: Router = Router::new().route(
+            "/",
+            any(|request: HttpRequest<AxumBody>| async move {
+                request.headers()[AUTHORIZATION]
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            }),
+        );
+
+        let signer = Arc::new(
+            OAuth2ClientCredentials::new(
+                ServiceSender::new(token_endpoint),
+                "http://auth.internal/token".parse()?,
+                "client id",
+                "secret",
+            )
+            .scope("read")
+            .scope("write"),
+        );
+        let sender = Auth::new(ServiceSender::new(backend), signer.to_owned());
+
+        let send = || async {
+            let request = HttpRequest::builder().uri("/").body(AxumBody::empty())?;
+            let response = sender.send(request).await?;
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            Ok::<_, Box<dyn std::error::Error>>(String::from_utf8(body.to_vec())?)
+        };
+
+        // Cached.
+        assert_eq!(send().await?, "Bearer token1");
+        assert_eq!(send().await?, "Bearer token1");
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // Expires within `refresh_before`, fetched each time.
+        signer.invalidate().await;
+        assert_eq!(send().await?, "Bearer token2");
+        assert_eq!(send().await?, "Bearer token3");
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+}
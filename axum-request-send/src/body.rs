@@ -0,0 +1,162 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body as AxumBody, BoxBody, Bytes, HttpBody},
+    http::HeaderMap,
+};
+use futures_util::stream;
+use http_body::SizeHint;
+use tokio::sync::oneshot;
+
+use crate::sender::BoxError;
+
+//
+/// Keeps `guard` alive until the body is dropped.
+pub struct GuardBody<G> {
+    inner: BoxBody,
+    _guard: G,
+}
+
+impl<G> GuardBody<G> {
+    pub fn new(inner: BoxBody, guard: G) -> Self {
+        Self {
+            inner,
+            _guard: guard,
+        }
+    }
+}
+
+impl<G> HttpBody for GuardBody<G>
+where
+    G: Unpin,
+{
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+//
+/// Collects a copy of a body, dropped past `max_size`.
+struct Tee {
+    tx: Option<oneshot::Sender<Bytes>>,
+    chunks: Vec<Bytes>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Tee {
+    fn new(max_size: usize, tx: oneshot::Sender<Bytes>) -> Self {
+        Self {
+            tx: Some(tx),
+            chunks: vec![],
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn push(&mut self, chunk: &Bytes) {
+        self.size += chunk.len();
+        if self.size > self.max_size {
+            self.tx = None;
+            self.chunks.clear();
+        } else if self.tx.is_some() {
+            self.chunks.push(chunk.to_owned());
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            tx.send(self.chunks.concat().into()).ok();
+        }
+    }
+}
+
+/// Forwards `body`, and sends a copy to `tx` once it is read to the end.
+///
+/// The copy is dropped when the body is larger than `max_size` or fails,
+/// trailers and the size hint are not kept.
+pub(crate) fn tee<B>(body: B, max_size: usize, tx: oneshot::Sender<Bytes>) -> AxumBody
+where
+    B: HttpBody<Data = Bytes> + Unpin + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    let stream = stream::unfold(
+        (body, Tee::new(max_size, tx)),
+        |(mut body, mut tee)| async move {
+            match body.data().await {
+                Some(Ok(chunk)) => {
+                    tee.push(&chunk);
+                    Some((Ok(chunk), (body, tee)))
+                }
+                Some(Err(err)) => {
+                    tee.tx = None;
+                    Some((Err(err), (body, tee)))
+                }
+                None => {
+                    tee.finish();
+                    None
+                }
+            }
+        },
+    );
+    AxumBody::wrap_stream(stream)
+}
+
+/// As `tee`, with the trailers, the body is forwarded by a task
+/// until the returned body is dropped. An error aborts the returned body.
+pub(crate) fn tee_with_trailers(
+    mut body: AxumBody,
+    max_size: usize,
+    tx: oneshot::Sender<Bytes>,
+) -> AxumBody {
+    let (mut body_tx, tee_body) = AxumBody::channel();
+    tokio::task::spawn(async move {
+        let mut tee = Tee::new(max_size, tx);
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(chunk) => {
+                    tee.push(&chunk);
+                    if body_tx.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                Err(_) => return body_tx.abort(),
+            }
+        }
+        match body.trailers().await {
+            Ok(Some(trailers)) => {
+                if body_tx.send_trailers(trailers).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(_) => return body_tx.abort(),
+        }
+        tee.finish();
+    });
+    tee_body
+}
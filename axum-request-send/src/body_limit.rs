@@ -0,0 +1,344 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use axum::{
+    body::{Body as AxumBody, Bytes, HttpBody},
+    http::{HeaderMap, Method, Request as HttpRequest, StatusCode},
+    response::Response as AxumResponse,
+};
+use futures_util::stream;
+use http_body::SizeHint;
+
+use crate::{
+    error::SendError,
+    framing::content_length,
+    sender::{BoxError, BoxFuture, RequestSender},
+    Config,
+};
+
+//
+/// The body went over `limit` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyTooLarge {
+    pub limit: u64,
+}
+
+impl core::fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "body is larger than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+//
+/// Limits the request and response body sizes, while streaming.
+///
+/// A request body over the limit fails with `SendError::PayloadTooLarge` (413),
+/// before it is forwarded when the length (`Content-Length` or the body size hint) is known,
+/// otherwise once the limit is reached while it is forwarded. An upstream that answers
+/// before reading past the limit of such a streamed body gets its response through,
+/// it has not received more than the limit.
+/// A response with a larger `Content-Length` (other than to `HEAD`, or a 1xx / 204 / 304)
+/// fails with `SendError::BodyStream` (502),
+/// a streamed one is aborted with a `BodyTooLarge` error.
+#[derive(Debug, Clone)]
+pub struct BodyLimit<S> {
+    inner: S,
+    max_request_body_size: Option<u64>,
+    max_response_body_size: Option<u64>,
+}
+
+impl<S> BodyLimit<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            max_request_body_size: None,
+            max_response_body_size: None,
+        }
+    }
+
+    pub fn max_request_body_size(mut self, max_request_body_size: u64) -> Self {
+        self.max_request_body_size = Some(max_request_body_size);
+        self
+    }
+
+    pub fn max_response_body_size(mut self, max_response_body_size: u64) -> Self {
+        self.max_response_body_size = Some(max_response_body_size);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> RequestSender for BodyLimit<S>
+where
+    S: RequestSender,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            let head = http_request.method() == Method::HEAD;
+            let exceeded = Arc::new(AtomicBool::new(false));
+            let http_request = match self.max_request_body_size {
+                Some(limit) => limit_request(http_request, limit, &exceeded)?,
+                None => http_request,
+            };
+
+            let result = self.inner.send_with_config(http_request, config).await;
+            // The upstream only got part of the body, its response or error is not kept.
+            if exceeded.load(Ordering::SeqCst) {
+                if let Some(limit) = self.max_request_body_size {
+                    return Err(SendError::PayloadTooLarge(BodyTooLarge { limit }.into()));
+                }
+            }
+            let response = result.map_err(Into::into)?;
+
+            match self.max_response_body_size {
+                Some(limit) => {
+                    // These have no body, whatever their `Content-Length`.
+                    let bodyless = head
+                        || response.status().is_informational()
+                        || response.status() == StatusCode::NO_CONTENT
+                        || response.status() == StatusCode::NOT_MODIFIED;
+                    if !bodyless && content_length(response.headers()).is_some_and(|x| x > limit) {
+                        return Err(SendError::BodyStream(BodyTooLarge { limit }.into()));
+                    }
+                    Ok(response
+                        .map(|body| axum::body::boxed(LimitBody::new(body, limit, Arc::default()))))
+                }
+                None => Ok(response),
+            }
+        })
+    }
+}
+
+fn limit_request(
+    http_request: HttpRequest<AxumBody>,
+    limit: u64,
+    exceeded: &Arc<AtomicBool>,
+) -> Result<HttpRequest<AxumBody>, SendError> {
+    let length =
+        content_length(http_request.headers()).or_else(|| http_request.body().size_hint().exact());
+    match length {
+        Some(length) if length > limit => {
+            Err(SendError::PayloadTooLarge(BodyTooLarge { limit }.into()))
+        }
+        // Framed, the body can not go over its length.
+        Some(_) => Ok(http_request),
+        None => Ok(http_request.map(|body| {
+            let body = LimitBody::new(body, limit, exceeded.to_owned());
+            let stream = stream::unfold(body, |mut body| async move {
+                body.data().await.map(|x| (x, body))
+            });
+            AxumBody::wrap_stream(stream)
+        })),
+    }
+}
+
+//
+/// Fails with `BodyTooLarge` once more than `limit` bytes are read, and sets `exceeded`.
+pub(crate) struct LimitBody<B> {
+    inner: B,
+    remaining: u64,
+    limit: u64,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl<B> LimitBody<B> {
+    pub(crate) fn new(inner: B, limit: u64, exceeded: Arc<AtomicBool>) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+            limit,
+            exceeded,
+        }
+    }
+}
+
+impl<B> HttpBody for LimitBody<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.exceeded.load(Ordering::SeqCst) {
+            return Poll::Ready(None);
+        }
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(chunk))) => match self.remaining.checked_sub(chunk.len() as u64) {
+                Some(remaining) => {
+                    self.remaining = remaining;
+                    Poll::Ready(Some(Ok(chunk)))
+                }
+                None => {
+                    self.exceeded.store(true, Ordering::SeqCst);
+                    Poll::Ready(Some(Err(BodyTooLarge { limit: self.limit }.into())))
+                }
+            },
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner)
+            .poll_trailers(cx)
+            .map_err(Into::into)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    use axum::{
+        http::{header::CONTENT_LENGTH, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+
+    use crate::impl_service::ServiceSender;
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let sender = BodyLimit::new(ServiceSender::new(
+            Router::new()
+                .route(
+                    "/upload",
+                    post({
+                        let calls = calls.to_owned();
+                        move |request: HttpRequest<AxumBody>| async move {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            match hyper::body::to_bytes(request.into_body()).await {
+                                Ok(body) => (StatusCode::OK, body.len().to_string()),
+                                Err(_) => (StatusCode::BAD_REQUEST, String::new()),
+                            }
+                        }
+                    }),
+                )
+                .route("/early", post(|| async { "early" }))
+                .route("/download", get(|| async { "0123456789" }))
+                .route(
+                    "/download_stream",
+                    get(|| async {
+                        let chunks = ["01234", "56789"].map(Ok::<_, std::io::Error>);
+                        axum::body::boxed(AxumBody::wrap_stream(stream::iter(chunks)))
+                    }),
+                ),
+        ))
+        .max_request_body_size(6)
+        .max_response_body_size(6);
+
+        // Known length, rejected before it is forwarded.
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("/upload")
+            .body(AxumBody::from("0123456789"))?;
+        let err = sender.send(request).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let chunks = ["01234", "56789"].map(Ok::<_, std::io::Error>);
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("/upload")
+            .header(CONTENT_LENGTH, "10")
+            .body(AxumBody::wrap_stream(stream::iter(chunks)))?;
+        let err = sender.send(request).await.unwrap_err();
+        assert!(matches!(err, SendError::PayloadTooLarge(_)));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("/upload")
+            .body(AxumBody::from("012345"))?;
+        let response = sender.send(request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "6");
+
+        // Streamed, rejected once the limit is reached.
+        let chunks = ["01234", "56789"].map(Ok::<_, std::io::Error>);
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("/upload")
+            .body(AxumBody::wrap_stream(stream::iter(chunks)))?;
+        let err = sender.send(request).await.unwrap_err();
+        assert!(matches!(err, SendError::PayloadTooLarge(_)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Streamed, the upstream answers without reading past the limit.
+        let chunks = ["01234", "56789"].map(Ok::<_, std::io::Error>);
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("/early")
+            .body(AxumBody::wrap_stream(stream::iter(chunks)))?;
+        let response = sender.send(request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "early");
+
+        // Response with a larger `Content-Length`.
+        let request = HttpRequest::builder()
+            .uri("/download")
+            .body(AxumBody::empty())?;
+        let err = sender.send(request).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+
+        // `HEAD` has the `Content-Length` of a `GET`, without a body.
+        let request = HttpRequest::builder()
+            .method("HEAD")
+            .uri("/download")
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert_eq!(response.headers().get(CONTENT_LENGTH).unwrap(), "10");
+        assert!(hyper::body::to_bytes(response.into_body())
+            .await?
+            .is_empty());
+
+        // Streamed response, aborted.
+        let request = HttpRequest::builder()
+            .uri("/download_stream")
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert!(response.headers().get(CONTENT_LENGTH).is_none());
+        let err = hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("larger than 6 bytes"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1011 @@
+use core::time::Duration;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use axum::{
+    body::{Body as AxumBody, Bytes, Full, HttpBody as _},
+    http::{
+        header::{
+            AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, DATE, ETAG, EXPIRES, HOST, IF_MATCH,
+            IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE, LAST_MODIFIED, PRAGMA,
+            RANGE, SET_COOKIE, VARY,
+        },
+        request::Parts as HttpRequestParts,
+        HeaderMap, HeaderName, HeaderValue, Method, Request as HttpRequest, StatusCode, Version,
+    },
+    response::{IntoResponse as _, Response as AxumResponse},
+};
+use futures_util::{stream, StreamExt as _};
+use tokio::sync::oneshot;
+
+use crate::{
+    body::tee,
+    cache_store::{CacheStore, CachedResponse, MemoryStore},
+    error::SendError,
+    framing::{content_length, normalize_response_framing},
+    retry::replay_extensions,
+    sender::{BoxFuture, RequestSender},
+    Config,
+};
+
+//
+/// A shared HTTP cache (RFC 9111) in front of `inner`.
+///
+/// `GET` responses are stored per effective request URI. With `Vary`, each variant is stored
+/// under the URI and the request headers it names, next to a marker under the URI alone.
+/// Stale responses are revalidated with `If-None-Match` / `If-Modified-Since`,
+/// or served while revalidating in the background within `stale-while-revalidate`,
+/// or served on upstream errors within `stale-if-error`.
+/// A successful unsafe request (e.g. `POST`) invalidates the stored response for its URI.
+///
+/// Responses with `Set-Cookie`, `Range` requests and bodies larger than `max_body_size`
+/// are not stored.
+#[derive(Clone)]
+pub struct Cache<S> {
+    inner: Arc<S>,
+    store: Arc<dyn CacheStore>,
+    max_body_size: usize,
+    revalidating: Arc<Mutex<HashSet<String>>>,
+}
+
+impl<S> core::fmt::Debug for Cache<S>
+where
+    S: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Cache")
+            .field("inner", &self.inner)
+            .field("max_body_size", &self.max_body_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Cache<S> {
+    /// With a `MemoryStore::default()`.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            store: Arc::new(MemoryStore::default()),
+            max_body_size: 1024 * 1024,
+            revalidating: Default::default(),
+        }
+    }
+
+    pub fn store(mut self, store: impl CacheStore + 'static) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> RequestSender for Cache<S>
+where
+    S: RequestSender + 'static,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            let (parts, body) = http_request.into_parts();
+            let key = cache_key(&parts);
+
+            if parts.method != Method::GET || parts.headers.contains_key(RANGE) {
+                let is_unsafe = !matches!(
+                    parts.method,
+                    Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+                );
+                let result = self
+                    .inner
+                    .send_with_config(HttpRequest::from_parts(parts, body), config)
+                    .await
+                    .map_err(Into::into);
+                // Ref https://www.rfc-editor.org/rfc/rfc9111#section-4.4
+                if let Ok(response) = &result {
+                    if is_unsafe
+                        && (response.status().is_success() || response.status().is_redirection())
+                    {
+                        self.store.remove(&key).await.ok();
+                    }
+                }
+                return result;
+            }
+
+            let request_cc = CacheControl::from_request(&parts.headers);
+            let (key, stored) = match self.lookup(key, &parts.headers).await {
+                (key, Some(stored)) => (key, stored),
+                (_, None) if request_cc.only_if_cached => {
+                    return Ok(StatusCode::GATEWAY_TIMEOUT.into_response())
+                }
+                (_, None) => {
+                    let request_time = SystemTime::now();
+                    let store = self.storable_request(&parts, &request_cc);
+                    let result = self
+                        .inner
+                        .send_with_config(HttpRequest::from_parts(parts, body), config)
+                        .await
+                        .map_err(Into::into);
+                    return Ok(match store {
+                        Some(store) => store.response(result?, request_time),
+                        None => result?,
+                    });
+                }
+            };
+
+            //
+            let now = SystemTime::now();
+            let response_cc = CacheControl::from_response(&stored.headers);
+            let age = current_age(&stored, now);
+            let lifetime = freshness_lifetime(&stored, &response_cc);
+            let staleness = age.saturating_sub(lifetime);
+            // `s-maxage` implies `proxy-revalidate`.
+            let must_revalidate = response_cc.must_revalidate
+                || response_cc.proxy_revalidate
+                || response_cc.s_maxage.is_some();
+
+            let fresh = age + secs(request_cc.min_fresh.unwrap_or(0)) < lifetime
+                && request_cc.max_age.map(|x| age <= secs(x)).unwrap_or(true);
+            let may_serve_stale = !response_cc.no_cache && !must_revalidate;
+            let client_accepts_stale = request_cc
+                .max_stale
+                .map(|x| staleness <= secs(x))
+                .unwrap_or(false);
+            let while_revalidate = response_cc
+                .stale_while_revalidate
+                .map(|x| staleness <= secs(x))
+                .unwrap_or(false);
+
+            if !request_cc.no_cache && !response_cc.no_cache && fresh {
+                return Ok(serve(&stored, now, parts.version, &parts.headers));
+            }
+            if !request_cc.no_cache && may_serve_stale && client_accepts_stale {
+                return Ok(serve(&stored, now, parts.version, &parts.headers));
+            }
+            if !request_cc.no_cache && may_serve_stale && while_revalidate {
+                self.revalidate_in_background(&key, &parts, &stored, config);
+                return Ok(serve(&stored, now, parts.version, &parts.headers));
+            }
+            if request_cc.only_if_cached {
+                return Ok(StatusCode::GATEWAY_TIMEOUT.into_response());
+            }
+
+            //
+            let stale_if_error = may_serve_stale
+                && [response_cc.stale_if_error, request_cc.stale_if_error]
+                    .into_iter()
+                    .flatten()
+                    .any(|x| staleness <= secs(x));
+            let version = parts.version;
+            let request_headers = parts.headers.to_owned();
+            let store = self.storable_request(&parts, &request_cc);
+
+            let request_time = SystemTime::now();
+            let http_request = conditional_request(parts, body, &stored);
+            let result = self
+                .inner
+                .send_with_config(http_request, config)
+                .await
+                .map_err(Into::into);
+            match result {
+                Ok(response) if response.status() == StatusCode::NOT_MODIFIED => {
+                    let stored = freshen(stored, response.headers(), request_time);
+                    self.store.put(&key, stored.to_owned()).await.ok();
+                    Ok(serve(&stored, SystemTime::now(), version, &request_headers))
+                }
+                Ok(response) if response.status().is_server_error() && stale_if_error => {
+                    Ok(serve(&stored, now, version, &request_headers))
+                }
+                Err(_) if stale_if_error => Ok(serve(&stored, now, version, &request_headers)),
+                result => Ok(match store {
+                    Some(store) => store.response(result?, request_time),
+                    None => result?,
+                }),
+            }
+        })
+    }
+}
+
+impl<S> Cache<S>
+where
+    S: RequestSender + 'static,
+    S::Error: Into<SendError>,
+{
+    /// The stored response for the request headers, with its key.
+    ///
+    /// A variant is ignored when it is older than the marker, e.g. stored before
+    /// an invalidation or a change of the `Vary` names.
+    async fn lookup(
+        &self,
+        key: String,
+        request_headers: &HeaderMap,
+    ) -> (String, Option<CachedResponse>) {
+        let stored = match self.store.get(&key).await {
+            Ok(Some(x)) => x,
+            _ => return (key, None),
+        };
+        if stored.vary.is_empty() {
+            return (key, Some(stored));
+        }
+
+        let vary = vary_values(&stored.vary, request_headers);
+        let variant_key = variant_key(&key, &vary);
+        match self.store.get(&variant_key).await {
+            Ok(Some(x)) if x.vary == vary && x.response_time >= stored.response_time => {
+                (variant_key, Some(x))
+            }
+            _ => (variant_key, None),
+        }
+    }
+
+    fn storable_request(
+        &self,
+        parts: &HttpRequestParts,
+        request_cc: &CacheControl,
+    ) -> Option<StoreResponse> {
+        if request_cc.no_store {
+            return None;
+        }
+        Some(StoreResponse {
+            store: self.store.to_owned(),
+            key: cache_key(parts),
+            request_headers: parts.headers.to_owned(),
+            max_body_size: self.max_body_size,
+        })
+    }
+
+    fn revalidate_in_background(
+        &self,
+        key: &str,
+        parts: &HttpRequestParts,
+        stored: &CachedResponse,
+        config: &Config,
+    ) {
+        if !self
+            .revalidating
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key.to_owned())
+        {
+            return;
+        }
+
+        let inner = self.inner.to_owned();
+        let store = self.storable_request(parts, &CacheControl::default());
+        let revalidating = self.revalidating.to_owned();
+        let key = key.to_owned();
+        let stored = stored.to_owned();
+        let config = config.to_owned();
+        let http_request = conditional_request(clone_parts(parts), AxumBody::empty(), &stored);
+        tokio::task::spawn(async move {
+            let request_time = SystemTime::now();
+            let result = inner
+                .send_with_config(http_request, &config)
+                .await
+                .map_err(Into::into);
+            match result {
+                Ok(response) if response.status() == StatusCode::NOT_MODIFIED => {
+                    let stored = freshen(stored, response.headers(), request_time);
+                    if let Some(store) = store {
+                        store.store.put(&key, stored).await.ok();
+                    }
+                }
+                Ok(response) if !response.status().is_server_error() => {
+                    if let Some(store) = store {
+                        let mut body = store.response(response, request_time).into_body();
+                        while let Some(Ok(_)) = body.data().await {}
+                    }
+                }
+                _ => {}
+            }
+            revalidating
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&key);
+        });
+    }
+}
+
+//
+struct StoreResponse {
+    store: Arc<dyn CacheStore>,
+    key: String,
+    request_headers: HeaderMap,
+    max_body_size: usize,
+}
+
+impl StoreResponse {
+    /// Stores the response once its body is read to the end, if it is storable.
+    fn response(self, response: AxumResponse, request_time: SystemTime) -> AxumResponse {
+        let response_time = SystemTime::now();
+        let response_cc = CacheControl::from_response(response.headers());
+        let vary = match vary_names(response.headers()) {
+            Some(names) => vary_values(&names, &self.request_headers),
+            None => return response,
+        };
+        let lifetime = freshness_lifetime(
+            &CachedResponse {
+                status: response.status(),
+                headers: response.headers().to_owned(),
+                body: Bytes::new(),
+                vary: vec![],
+                request_time,
+                response_time,
+            },
+            &response_cc,
+        );
+        if !is_storable(response.status(), response.headers(), &response_cc)
+            || (self.request_headers.contains_key(AUTHORIZATION)
+                && !response_cc.public
+                && !response_cc.must_revalidate
+                && response_cc.s_maxage.is_none())
+            || (lifetime.is_zero()
+                && response_cc.stale_while_revalidate.is_none()
+                && !response.headers().contains_key(ETAG)
+                && !response.headers().contains_key(LAST_MODIFIED))
+            || content_length(response.headers())
+                .map(|x| x > self.max_body_size as u64)
+                .unwrap_or(false)
+        {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let (tx, rx) = oneshot::channel();
+        let body = tee(body, self.max_body_size, tx);
+        let status = parts.status;
+        let headers = parts.headers.to_owned();
+        // Stored before the end of the body, a following request sees it.
+        let put = stream::once(async move {
+            if let Ok(body) = rx.await {
+                let stored = CachedResponse {
+                    status,
+                    headers,
+                    body,
+                    vary,
+                    request_time,
+                    response_time,
+                };
+                self.put(stored).await;
+            }
+        })
+        .filter_map(|()| async { None });
+        let body = AxumBody::wrap_stream(body.chain(put));
+        AxumResponse::from_parts(parts, axum::body::boxed(body))
+    }
+
+    async fn put(&self, stored: CachedResponse) {
+        if stored.vary.is_empty() {
+            self.store.put(&self.key, stored).await.ok();
+            return;
+        }
+
+        // A new marker for new `Vary` names, older variants are then ignored.
+        let same_names = |marker: &CachedResponse| {
+            marker.vary.len() == stored.vary.len()
+                && marker
+                    .vary
+                    .iter()
+                    .zip(&stored.vary)
+                    .all(|((x, _), (y, _))| x == y)
+        };
+        match self.store.get(&self.key).await {
+            Ok(Some(marker)) if same_names(&marker) => {}
+            _ => {
+                let marker = CachedResponse {
+                    body: Bytes::new(),
+                    ..stored.to_owned()
+                };
+                self.store.put(&self.key, marker).await.ok();
+            }
+        }
+        let variant_key = variant_key(&self.key, &stored.vary);
+        self.store.put(&variant_key, stored).await.ok();
+    }
+}
+
+//
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    must_revalidate: bool,
+    proxy_revalidate: bool,
+    only_if_cached: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+    max_stale: Option<u64>,
+    min_fresh: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+    stale_if_error: Option<u64>,
+}
+
+impl CacheControl {
+    fn from_request(headers: &HeaderMap) -> Self {
+        let mut cc = Self::from_response(headers);
+        // Ref https://www.rfc-editor.org/rfc/rfc9111#section-5.4
+        if !headers.contains_key(CACHE_CONTROL)
+            && headers
+                .get_all(PRAGMA)
+                .iter()
+                .filter_map(|x| x.to_str().ok())
+                .any(|x| x.to_ascii_lowercase().contains("no-cache"))
+        {
+            cc.no_cache = true;
+        }
+        cc
+    }
+
+    /// `no-cache="field"` and `private="field"` are treated as unqualified.
+    fn from_response(headers: &HeaderMap) -> Self {
+        let mut cc = Self::default();
+        for value in headers.get_all(CACHE_CONTROL) {
+            let value = match value.to_str() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            for directive in value.split(',') {
+                let (name, arg) = match directive.split_once('=') {
+                    Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                    None => (directive.trim(), None),
+                };
+                let secs = arg.and_then(|x| x.parse::<u64>().ok());
+                match name.to_ascii_lowercase().as_str() {
+                    "no-store" => cc.no_store = true,
+                    "no-cache" => cc.no_cache = true,
+                    "private" => cc.private = true,
+                    "public" => cc.public = true,
+                    "must-revalidate" => cc.must_revalidate = true,
+                    "proxy-revalidate" => cc.proxy_revalidate = true,
+                    "only-if-cached" => cc.only_if_cached = true,
+                    // An invalid value is treated as stale.
+                    "max-age" => cc.max_age = Some(secs.unwrap_or(0)),
+                    "s-maxage" => cc.s_maxage = Some(secs.unwrap_or(0)),
+                    "max-stale" => cc.max_stale = Some(secs.unwrap_or(u64::MAX)),
+                    "min-fresh" => cc.min_fresh = secs,
+                    "stale-while-revalidate" => cc.stale_while_revalidate = secs,
+                    "stale-if-error" => cc.stale_if_error = secs,
+                    _ => {}
+                }
+            }
+        }
+        cc
+    }
+}
+
+// Ref https://www.rfc-editor.org/rfc/rfc9110#section-15.1
+fn is_heuristically_cacheable(status: StatusCode) -> bool {
+    matches!(
+        status.as_u16(),
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+// Ref https://www.rfc-editor.org/rfc/rfc9111#section-3
+fn is_storable(status: StatusCode, headers: &HeaderMap, response_cc: &CacheControl) -> bool {
+    let has_explicit_freshness = response_cc.max_age.is_some()
+        || response_cc.s_maxage.is_some()
+        || headers.contains_key(EXPIRES)
+        || response_cc.public;
+    !response_cc.no_store
+        && !response_cc.private
+        && !headers.contains_key(SET_COOKIE)
+        && status != StatusCode::PARTIAL_CONTENT
+        && status != StatusCode::NOT_MODIFIED
+        && !status.is_informational()
+        && (is_heuristically_cacheable(status) || has_explicit_freshness)
+}
+
+// Ref https://www.rfc-editor.org/rfc/rfc9111#section-4.2.1
+fn freshness_lifetime(stored: &CachedResponse, response_cc: &CacheControl) -> Duration {
+    if let Some(x) = response_cc.s_maxage.or(response_cc.max_age) {
+        return secs(x);
+    }
+
+    let date = header_date(&stored.headers, &DATE).unwrap_or(stored.response_time);
+    if let Some(expires) = stored.headers.get(EXPIRES) {
+        // An invalid date (e.g. `0`) is in the past.
+        return expires
+            .to_str()
+            .ok()
+            .and_then(|x| httpdate::parse_http_date(x).ok())
+            .and_then(|x| x.duration_since(date).ok())
+            .unwrap_or_default();
+    }
+
+    // Ref https://www.rfc-editor.org/rfc/rfc9111#section-4.2.2
+    match header_date(&stored.headers, &LAST_MODIFIED) {
+        Some(last_modified) if is_heuristically_cacheable(stored.status) => {
+            (date.duration_since(last_modified).unwrap_or_default() / 10)
+                .min(Duration::from_secs(24 * 60 * 60))
+        }
+        _ => Duration::ZERO,
+    }
+}
+
+// Ref https://www.rfc-editor.org/rfc/rfc9111#section-4.2.3
+fn current_age(stored: &CachedResponse, now: SystemTime) -> Duration {
+    let date = header_date(&stored.headers, &DATE).unwrap_or(stored.response_time);
+    let age_value = stored
+        .headers
+        .get(AGE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .map(secs)
+        .unwrap_or_default();
+
+    let apparent_age = stored
+        .response_time
+        .duration_since(date)
+        .unwrap_or_default();
+    let response_delay = stored
+        .response_time
+        .duration_since(stored.request_time)
+        .unwrap_or_default();
+    let corrected_initial_age = apparent_age.max(age_value + response_delay);
+    let resident_time = now.duration_since(stored.response_time).unwrap_or_default();
+    corrected_initial_age + resident_time
+}
+
+fn secs(x: u64) -> Duration {
+    Duration::from_secs(x)
+}
+
+fn header_date(headers: &HeaderMap, name: &HeaderName) -> Option<SystemTime> {
+    httpdate::parse_http_date(headers.get(name)?.to_str().ok()?).ok()
+}
+
+//
+fn cache_key(parts: &HttpRequestParts) -> String {
+    match (parts.uri.authority(), parts.headers.get(HOST)) {
+        (None, Some(host)) => format!("{}{}", String::from_utf8_lossy(host.as_bytes()), parts.uri),
+        _ => parts.uri.to_string(),
+    }
+}
+
+/// Only used to find the variant, its `vary` is compared on lookup.
+fn variant_key(key: &str, vary: &[(HeaderName, Option<HeaderValue>)]) -> String {
+    let mut variant_key = key.to_owned();
+    for (name, value) in vary {
+        variant_key.push(' ');
+        variant_key.push_str(name.as_str());
+        if let Some(value) = value {
+            variant_key.push(':');
+            variant_key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+        }
+    }
+    variant_key
+}
+
+/// `None` for `Vary: *`.
+fn vary_names(headers: &HeaderMap) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    let mut names = vec![];
+    for value in headers.get_all(VARY) {
+        for name in value.to_str().ok()?.split(',') {
+            let name = name.trim();
+            if name == "*" {
+                return None;
+            }
+            if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+                if !names.iter().any(|(x, _)| *x == name) {
+                    names.push((name, None));
+                }
+            }
+        }
+    }
+    Some(names)
+}
+
+fn vary_values(
+    names: &[(HeaderName, Option<HeaderValue>)],
+    request_headers: &HeaderMap,
+) -> Vec<(HeaderName, Option<HeaderValue>)> {
+    names
+        .iter()
+        .map(|(name, _)| {
+            let values = request_headers
+                .get_all(name)
+                .iter()
+                .map(|x| x.as_bytes())
+                .collect::<Vec<_>>();
+            let value = if values.is_empty() {
+                None
+            } else {
+                HeaderValue::from_bytes(&values.join(&b", "[..])).ok()
+            };
+            (name.to_owned(), value)
+        })
+        .collect()
+}
+
+fn clone_parts(parts: &HttpRequestParts) -> HttpRequestParts {
+    let mut clone = HttpRequest::new(()).into_parts().0;
+    clone.method = parts.method.to_owned();
+    clone.uri = parts.uri.to_owned();
+    clone.version = parts.version;
+    clone.headers = parts.headers.to_owned();
+    clone.extensions = replay_extensions(&parts.extensions);
+    clone
+}
+
+/// The client's own preconditions are replaced by the stored validators.
+fn conditional_request(
+    mut parts: HttpRequestParts,
+    body: AxumBody,
+    stored: &CachedResponse,
+) -> HttpRequest<AxumBody> {
+    for name in [
+        IF_MATCH,
+        IF_NONE_MATCH,
+        IF_MODIFIED_SINCE,
+        IF_UNMODIFIED_SINCE,
+        IF_RANGE,
+    ] {
+        parts.headers.remove(name);
+    }
+    if let Some(etag) = stored.headers.get(ETAG) {
+        parts.headers.insert(IF_NONE_MATCH, etag.to_owned());
+    }
+    if let Some(last_modified) = stored.headers.get(LAST_MODIFIED) {
+        parts
+            .headers
+            .insert(IF_MODIFIED_SINCE, last_modified.to_owned());
+    }
+    HttpRequest::from_parts(parts, body)
+}
+
+// Ref https://www.rfc-editor.org/rfc/rfc9111#section-4.3.4
+fn freshen(
+    mut stored: CachedResponse,
+    headers: &HeaderMap,
+    request_time: SystemTime,
+) -> CachedResponse {
+    stored.headers.remove(AGE);
+    for name in headers.keys() {
+        if name == CONTENT_LENGTH {
+            continue;
+        }
+        stored.headers.remove(name);
+        for value in headers.get_all(name) {
+            stored.headers.append(name.to_owned(), value.to_owned());
+        }
+    }
+    stored.request_time = request_time;
+    stored.response_time = SystemTime::now();
+    stored
+}
+
+fn serve(
+    stored: &CachedResponse,
+    now: SystemTime,
+    version: Version,
+    request_headers: &HeaderMap,
+) -> AxumResponse {
+    let mut response = AxumResponse::new(());
+    *response.headers_mut() = stored.headers.to_owned();
+    response
+        .headers_mut()
+        .insert(AGE, HeaderValue::from(current_age(stored, now).as_secs()));
+
+    let body =
+        if stored.status == StatusCode::OK && is_not_modified(request_headers, &stored.headers) {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            response.headers_mut().remove(CONTENT_LENGTH);
+            Bytes::new()
+        } else {
+            *response.status_mut() = stored.status;
+            response
+                .headers_mut()
+                .insert(CONTENT_LENGTH, HeaderValue::from(stored.body.len()));
+            stored.body.to_owned()
+        };
+    normalize_response_framing(&mut response, version);
+
+    let (parts, _) = response.into_parts();
+    AxumResponse::from_parts(parts, axum::body::boxed(Full::new(body)))
+}
+
+// Ref https://www.rfc-editor.org/rfc/rfc9110#section-13.1.2
+fn is_not_modified(request_headers: &HeaderMap, headers: &HeaderMap) -> bool {
+    let opaque = |x: &str| x.trim().trim_start_matches("W/").to_owned();
+
+    if request_headers.contains_key(IF_NONE_MATCH) {
+        let etag = match headers.get(ETAG).and_then(|x| x.to_str().ok()) {
+            Some(x) => opaque(x),
+            None => return false,
+        };
+        return request_headers
+            .get_all(IF_NONE_MATCH)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','))
+            .any(|x| x.trim() == "*" || opaque(x) == etag);
+    }
+
+    match (
+        header_date(request_headers, &IF_MODIFIED_SINCE),
+        header_date(headers, &LAST_MODIFIED),
+    ) {
+        (Some(since), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{extract::State, http::header::ACCEPT, routing::get, Router};
+
+    use crate::impl_service::ServiceSender;
+
+    fn stored(headers: &[(HeaderName, &str)], response_time: SystemTime) -> CachedResponse {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.append(name.to_owned(), value.parse().unwrap());
+        }
+        CachedResponse {
+            status: StatusCode::OK,
+            headers: header_map,
+            body: Bytes::new(),
+            vary: vec![],
+            request_time: response_time - Duration::from_secs(1),
+            response_time,
+        }
+    }
+
+    #[test]
+    fn test_freshness() {
+        let response_time = httpdate::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        let now = response_time + Duration::from_secs(100);
+
+        let x = stored(
+            &[
+                (CACHE_CONTROL, "public, max-age=60, s-maxage=\"120\""),
+                (AGE, "10"),
+            ],
+            response_time,
+        );
+        let cc = CacheControl::from_response(&x.headers);
+        assert!(cc.public);
+        assert_eq!(freshness_lifetime(&x, &cc), Duration::from_secs(120));
+        // Age plus the response delay, plus the resident time.
+        assert_eq!(current_age(&x, now), Duration::from_secs(111));
+
+        let x = stored(
+            &[
+                (DATE, "Sun, 06 Nov 1994 08:49:27 GMT"),
+                (EXPIRES, "Sun, 06 Nov 1994 08:50:27 GMT"),
+            ],
+            response_time,
+        );
+        let cc = CacheControl::from_response(&x.headers);
+        assert_eq!(freshness_lifetime(&x, &cc), Duration::from_secs(60));
+        assert_eq!(current_age(&x, now), Duration::from_secs(110));
+
+        let x = stored(&[(EXPIRES, "0")], response_time);
+        assert_eq!(
+            freshness_lifetime(&x, &CacheControl::default()),
+            Duration::ZERO
+        );
+
+        let x = stored(
+            &[(LAST_MODIFIED, "Sun, 06 Nov 1994 07:49:37 GMT")],
+            response_time,
+        );
+        assert_eq!(
+            freshness_lifetime(&x, &CacheControl::default()),
+            Duration::from_secs(360)
+        );
+
+        //
+        let mut headers = HeaderMap::new();
+        headers.insert(PRAGMA, "no-cache".parse().unwrap());
+        assert!(CacheControl::from_request(&headers).no_cache);
+        headers.insert(CACHE_CONTROL, "max-stale, min-fresh=5".parse().unwrap());
+        let cc = CacheControl::from_request(&headers);
+        assert!(!cc.no_cache);
+        assert_eq!(cc.max_stale, Some(u64::MAX));
+        assert_eq!(cc.min_fresh, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        #[derive(Clone, Default)]
+        struct Calls(Arc<Mutex<Vec<String>>>);
+
+        impl Calls {
+            fn take(&self) -> Vec<String> {
+                core::mem::take(&mut *self.0.lock().unwrap())
+            }
+        }
+
+        async fn handler(
+            State(calls): State<Calls>,
+            request: HttpRequest<AxumBody>,
+        ) -> AxumResponse {
+            let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+            let mut log = calls.0.lock().unwrap();
+            log.push(format!(
+                "{} {}{}",
+                request.method(),
+                request.uri().path(),
+                if if_none_match.is_some() {
+                    " revalidate"
+                } else {
+                    ""
+                }
+            ));
+            let n = log.len();
+            drop(log);
+
+            let cache_control = match request.uri().path() {
+                "/fresh" => "max-age=60",
+                "/no-cache" => "no-cache",
+                "/swr" => "max-age=0, stale-while-revalidate=60",
+                _ => "private, max-age=60",
+            };
+            if if_none_match
+                .as_ref()
+                .map(|x| x == "\"v1\"")
+                .unwrap_or(false)
+            {
+                return (
+                    StatusCode::NOT_MODIFIED,
+                    [(CACHE_CONTROL, cache_control), (ETAG, "\"v1\"")],
+                )
+                    .into_response();
+            }
+            (
+                [
+                    (CACHE_CONTROL, cache_control),
+                    (ETAG, "\"v1\""),
+                    (VARY, "accept"),
+                ],
+                format!("{} {n}", request.uri().path()),
+            )
+                .into_response()
+        }
+
+        let calls = Calls::default();
+        let backend = Router::new()
+            .route("/:path", get(handler).post(handler))
+            .with_state(calls.to_owned());
+        let sender = Cache::new(ServiceSender::new(backend));
+
+        let send = |method: Method, uri: &'static str, headers: &[(HeaderName, &str)]| {
+            let mut request = HttpRequest::builder().method(method).uri(uri);
+            for (name, value) in headers {
+                request = request.header(name, *value);
+            }
+            let request = request.body(AxumBody::empty()).unwrap();
+            let sender = &sender;
+            async move {
+                let response = sender.send(request).await.unwrap();
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        // Fresh, served from the store.
+        assert_eq!(
+            send(Method::GET, "http://backend/fresh", &[]).await.1,
+            "/fresh 1"
+        );
+        assert_eq!(
+            send(Method::GET, "http://backend/fresh", &[]).await.1,
+            "/fresh 1"
+        );
+        assert_eq!(calls.take(), ["GET /fresh"]);
+
+        // The client's conditional request is answered from the store.
+        let (status, _) = send(
+            Method::GET,
+            "http://backend/fresh",
+            &[(IF_NONE_MATCH, "W/\"v1\"")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert!(calls.take().is_empty());
+
+        // Another variant.
+        assert_eq!(
+            send(
+                Method::GET,
+                "http://backend/fresh",
+                &[(ACCEPT, "text/plain")]
+            )
+            .await
+            .1,
+            "/fresh 1"
+        );
+        assert_eq!(calls.take(), ["GET /fresh"]);
+        // Both variants are kept.
+        send(Method::GET, "http://backend/fresh", &[]).await;
+        send(
+            Method::GET,
+            "http://backend/fresh",
+            &[(ACCEPT, "text/plain")],
+        )
+        .await;
+        assert!(calls.take().is_empty());
+
+        // Invalidated by an unsafe method, all variants.
+        send(Method::POST, "http://backend/fresh", &[]).await;
+        assert_eq!(
+            send(Method::GET, "http://backend/fresh", &[]).await.1,
+            "/fresh 2"
+        );
+        assert_eq!(calls.take(), ["POST /fresh", "GET /fresh"]);
+        send(
+            Method::GET,
+            "http://backend/fresh",
+            &[(ACCEPT, "text/plain")],
+        )
+        .await;
+        assert_eq!(calls.take(), ["GET /fresh"]);
+
+        // Revalidated on each request.
+        assert_eq!(
+            send(Method::GET, "http://backend/no-cache", &[]).await.1,
+            "/no-cache 1"
+        );
+        assert_eq!(
+            send(Method::GET, "http://backend/no-cache", &[]).await.1,
+            "/no-cache 1"
+        );
+        assert_eq!(calls.take(), ["GET /no-cache", "GET /no-cache revalidate"]);
+
+        // Stale, served while revalidating in the background.
+        assert_eq!(
+            send(Method::GET, "http://backend/swr", &[]).await.1,
+            "/swr 1"
+        );
+        assert_eq!(
+            send(Method::GET, "http://backend/swr", &[]).await.1,
+            "/swr 1"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.take(), ["GET /swr", "GET /swr revalidate"]);
+
+        // Not stored.
+        assert_eq!(
+            send(Method::GET, "http://backend/private", &[]).await.1,
+            "/private 1"
+        );
+        assert_eq!(
+            send(Method::GET, "http://backend/private", &[]).await.1,
+            "/private 2"
+        );
+        assert_eq!(
+            send(
+                Method::GET,
+                "http://backend/other",
+                &[(CACHE_CONTROL, "only-if-cached")]
+            )
+            .await
+            .0,
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(calls.take(), ["GET /private", "GET /private"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+};
+use bytes::Buf as _;
+
+use crate::sender::BoxFuture;
+
+//
+/// A stored response, with what is needed to compute its age and match its `Vary`.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// The request headers named by `Vary`, `None` when absent from the request.
+    pub vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    pub request_time: SystemTime,
+    pub response_time: SystemTime,
+}
+
+impl CachedResponse {
+    fn size(&self) -> usize {
+        self.body.len()
+            + self
+                .headers
+                .iter()
+                .map(|(k, v)| k.as_str().len() + v.len())
+                .sum::<usize>()
+    }
+}
+
+//
+/// Where `Cache` keeps responses, keyed by the effective request URI
+/// (followed by the `Vary` request headers for a variant).
+///
+/// Errors are treated as a miss.
+pub trait CacheStore: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<CachedResponse>, IoError>>;
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        response: CachedResponse,
+    ) -> BoxFuture<'a, Result<(), IoError>>;
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), IoError>>;
+}
+
+//
+/// In-memory store, least recently used responses are evicted past `max_size` bytes.
+#[derive(Debug)]
+pub struct MemoryStore {
+    max_size: usize,
+    inner: Mutex<MemoryStoreInner>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryStoreInner {
+    entries: HashMap<String, (CachedResponse, u64)>,
+    // tick -> key, the first one is the least recently used.
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    size: usize,
+}
+
+impl MemoryStore {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            inner: Default::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entries
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new(64 * 1024 * 1024)
+    }
+}
+
+impl MemoryStoreInner {
+    fn remove(&mut self, key: &str) {
+        if let Some((response, tick)) = self.entries.remove(key) {
+            self.recency.remove(&tick);
+            self.size -= response.size();
+        }
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<CachedResponse>, IoError>> {
+        Box::pin(async move {
+            let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            inner.tick += 1;
+            let tick = inner.tick;
+            let old_tick = match inner.entries.get_mut(key) {
+                Some((_, x)) => core::mem::replace(x, tick),
+                None => return Ok(None),
+            };
+            inner.recency.remove(&old_tick);
+            inner.recency.insert(tick, key.to_owned());
+            Ok(inner.entries.get(key).map(|(x, _)| x.to_owned()))
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        response: CachedResponse,
+    ) -> BoxFuture<'a, Result<(), IoError>> {
+        Box::pin(async move {
+            let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            inner.remove(key);
+            let size = response.size();
+            if size > self.max_size {
+                return Ok(());
+            }
+            while inner.size + size > self.max_size {
+                let lru = match inner.recency.values().next() {
+                    Some(x) => x.to_owned(),
+                    None => break,
+                };
+                inner.remove(&lru);
+            }
+
+            inner.tick += 1;
+            let tick = inner.tick;
+            inner.recency.insert(tick, key.to_owned());
+            inner.entries.insert(key.to_owned(), (response, tick));
+            inner.size += size;
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), IoError>> {
+        Box::pin(async move {
+            self.inner
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(key);
+            Ok(())
+        })
+    }
+}
+
+//
+/// On-disk store, one file per key in `dir`.
+///
+/// Files are replaced atomically, there is no eviction, stale files are overwritten
+/// or removed by the `Cache`.
+#[derive(Debug, Clone)]
+pub struct DiskStore {
+    dir: PathBuf,
+}
+
+const DISK_STORE_MAGIC: &[u8] = b"axum-request-send cache 1\n";
+
+impl DiskStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        // FNV-1a, the key is stored in the file and checked on read.
+        let hash = key.bytes().fold(0xcbf29ce484222325_u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        self.dir.join(format!("{hash:016x}"))
+    }
+}
+
+impl CacheStore for DiskStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<CachedResponse>, IoError>> {
+        Box::pin(async move {
+            let bytes = match tokio::fs::read(self.path(key)).await {
+                Ok(x) => x,
+                Err(err) if err.kind() == IoErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            match decode(key, Bytes::from(bytes)) {
+                Some(x) => Ok(Some(x)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        response: CachedResponse,
+    ) -> BoxFuture<'a, Result<(), IoError>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            let path = self.path(key);
+            let tmp_path = path.with_extension(format!("tmp{}", rand::random::<u32>()));
+            tokio::fs::write(&tmp_path, encode(key, &response)).await?;
+            if let Err(err) = tokio::fs::rename(&tmp_path, &path).await {
+                tokio::fs::remove_file(&tmp_path).await.ok();
+                return Err(err);
+            }
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), IoError>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path(key)).await {
+                Err(err) if err.kind() != IoErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/*
+magic
+key
+status request_time_ms response_time_ms vary_count header_count
+vary lines: `name` (absent) or `name:value`
+header lines: `name:value`
+body
+*/
+fn encode(key: &str, response: &CachedResponse) -> Vec<u8> {
+    let millis = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    };
+
+    let mut bytes = Vec::with_capacity(response.size() + 256);
+    bytes.extend_from_slice(DISK_STORE_MAGIC);
+    bytes.extend_from_slice(key.as_bytes());
+    bytes.push(b'\n');
+    bytes.extend_from_slice(
+        format!(
+            "{} {} {} {} {}\n",
+            response.status.as_u16(),
+            millis(response.request_time),
+            millis(response.response_time),
+            response.vary.len(),
+            response.headers.len()
+        )
+        .as_bytes(),
+    );
+    for (name, value) in &response.vary {
+        bytes.extend_from_slice(name.as_str().as_bytes());
+        if let Some(value) = value {
+            bytes.push(b':');
+            bytes.extend_from_slice(value.as_bytes());
+        }
+        bytes.push(b'\n');
+    }
+    for (name, value) in &response.headers {
+        bytes.extend_from_slice(name.as_str().as_bytes());
+        bytes.push(b':');
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(b'\n');
+    }
+    bytes.extend_from_slice(&response.body);
+    bytes
+}
+
+fn decode(key: &str, mut bytes: Bytes) -> Option<CachedResponse> {
+    fn line(bytes: &mut Bytes) -> Option<Bytes> {
+        let end = bytes.iter().position(|b| *b == b'\n')?;
+        let line = bytes.split_to(end);
+        bytes.advance(1);
+        Some(line)
+    }
+    fn header(line: &[u8]) -> Option<(HeaderName, Option<HeaderValue>)> {
+        match line.iter().position(|b| *b == b':') {
+            Some(i) => Some((
+                HeaderName::from_bytes(&line[..i]).ok()?,
+                Some(HeaderValue::from_bytes(&line[i + 1..]).ok()?),
+            )),
+            None => Some((HeaderName::from_bytes(line).ok()?, None)),
+        }
+    }
+
+    if !bytes.starts_with(DISK_STORE_MAGIC) {
+        return None;
+    }
+    bytes.advance(DISK_STORE_MAGIC.len());
+    if line(&mut bytes)? != key.as_bytes() {
+        return None;
+    }
+
+    let meta = line(&mut bytes)?;
+    let meta = core::str::from_utf8(&meta).ok()?;
+    let mut meta = meta.split(' ');
+    let mut next = || meta.next()?.parse::<u64>().ok();
+    let status = StatusCode::from_u16(next()? as u16).ok()?;
+    let request_time = UNIX_EPOCH + Duration::from_millis(next()?);
+    let response_time = UNIX_EPOCH + Duration::from_millis(next()?);
+    let vary_count = next()?;
+    let header_count = next()?;
+
+    let mut vary = vec![];
+    for _ in 0..vary_count {
+        vary.push(header(&line(&mut bytes)?)?);
+    }
+    let mut headers = HeaderMap::new();
+    for _ in 0..header_count {
+        let (name, value) = header(&line(&mut bytes)?)?;
+        headers.append(name, value?);
+    }
+
+    Some(CachedResponse {
+        status,
+        headers,
+        body: bytes,
+        vary,
+        request_time,
+        response_time,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::header::{CONTENT_TYPE, SET_COOKIE, VARY};
+
+    fn response(body: &str) -> CachedResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("a=1"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("b=2"));
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding, accept"));
+        CachedResponse {
+            status: StatusCode::OK,
+            headers,
+            body: Bytes::copy_from_slice(body.as_bytes()),
+            vary: vec![
+                (
+                    HeaderName::from_static("accept-encoding"),
+                    Some(HeaderValue::from_static("gzip")),
+                ),
+                (HeaderName::from_static("accept"), None),
+            ],
+            request_time: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000),
+            response_time: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() -> Result<(), IoError> {
+        let size = response("0123456789").size();
+        let store = MemoryStore::new(size * 2);
+
+        store.put("a", response("0123456789")).await?;
+        store.put("b", response("0123456789")).await?;
+        assert!(store.get("a").await?.is_some());
+        // `b` is the least recently used.
+        store.put("c", response("0123456789")).await?;
+        assert_eq!(store.len(), 2);
+        assert!(store.get("a").await?.is_some());
+        assert!(store.get("b").await?.is_none());
+        assert!(store.get("c").await?.is_some());
+
+        store.remove("a").await?;
+        assert_eq!(store.len(), 1);
+
+        // Larger than the store.
+        store.put("d", response(&"0".repeat(size * 2))).await?;
+        assert!(store.get("d").await?.is_none());
+        assert_eq!(store.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disk_store() -> Result<(), IoError> {
+        let dir =
+            std::env::temp_dir().join(format!("axum-request-send-cache-{}", rand::random::<u64>()));
+        let store = DiskStore::new(&dir);
+
+        assert!(store.get("http://example.com/").await?.is_none());
+        store
+            .put("http://example.com/", response("foo\nbar"))
+            .await?;
+        let stored = store.get("http://example.com/").await?.unwrap();
+        let expected = response("foo\nbar");
+        assert_eq!(stored.status, expected.status);
+        assert_eq!(stored.headers, expected.headers);
+        assert_eq!(stored.body, expected.body);
+        assert_eq!(stored.vary, expected.vary);
+        assert_eq!(stored.request_time, expected.request_time);
+        assert_eq!(stored.response_time, expected.response_time);
+
+        // Another key with the same file is a miss.
+        assert!(decode(
+            "http://example.com/x",
+            Bytes::from(encode("http://example.com/", &expected))
+        )
+        .is_none());
+
+        store.remove("http://example.com/").await?;
+        assert!(store.get("http://example.com/").await?.is_none());
+        store.remove("http://example.com/").await?;
+
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
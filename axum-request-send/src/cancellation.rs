@@ -0,0 +1,273 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// The send future was dropped before the response head, e.g. the client disconnected.
+    Request,
+    /// The response body was dropped before its end.
+    ResponseBody,
+}
+
+type OnCancel = Arc<dyn Fn(CancelReason) + Send + Sync>;
+
+//
+/// Records a cancelled upstream call, insert it into the request extensions
+/// (`ReverseProxy` inserts one when there is none).
+///
+/// When the client disconnects, the server drops the send future or the response body,
+/// which aborts the upstream request. `impl_hyper`, `impl_isahc`, `impl_reqwest`
+/// and `ServiceSender` record that here, only the first reason is kept.
+///
+/// A shared isahc `HttpClient` only aborts a request waiting for its response head
+/// once the head arrives, `impl_isahc::AbortableClient` is an opt-in alternative.
+#[derive(Clone, Default)]
+pub struct Cancellation {
+    reason: Arc<Mutex<Option<CancelReason>>>,
+    on_cancel: Option<OnCancel>,
+    // Set for an attempt, see `attempt`.
+    parent: Arc<Mutex<Option<Cancellation>>>,
+}
+
+impl core::fmt::Debug for Cancellation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Cancellation")
+            .field("reason", &self.reason())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_cancel(mut self, f: impl Fn(CancelReason) + Send + Sync + 'static) -> Self {
+        self.on_cancel = Some(Arc::new(f));
+        self
+    }
+
+    /// A new, not cancelled, handle sharing the `on_cancel` callback.
+    pub(crate) fn renew(&self) -> Self {
+        Self {
+            reason: Arc::default(),
+            on_cancel: self.on_cancel.to_owned(),
+            parent: Arc::default(),
+        }
+    }
+
+    /// A handle for one of several attempts, its reason is also recorded here until `detach`.
+    pub(crate) fn attempt(&self) -> Self {
+        Self {
+            reason: Arc::default(),
+            on_cancel: None,
+            parent: Arc::new(Mutex::new(Some(self.to_owned()))),
+        }
+    }
+
+    /// Stops forwarding to the `attempt` parent, e.g. before dropping a response that is retried.
+    pub(crate) fn detach(&self) {
+        self.parent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+
+    pub fn reason(&self) -> Option<CancelReason> {
+        *self.reason.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.reason().is_some()
+    }
+}
+
+//
+pub(crate) use self::guard::{guard_body, CancelGuard};
+
+mod guard {
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use std::sync::PoisonError;
+
+    use axum::{
+        body::{BoxBody, Bytes, HttpBody},
+        http::HeaderMap,
+    };
+    use http_body::SizeHint;
+
+    use super::{CancelReason, Cancellation};
+    use crate::sender::BoxError;
+
+    impl Cancellation {
+        /// Only the first reason is kept.
+        fn record(&self, reason: CancelReason) {
+            {
+                let mut current = self.reason.lock().unwrap_or_else(PoisonError::into_inner);
+                if current.is_some() {
+                    return;
+                }
+                *current = Some(reason);
+            }
+            if let Some(on_cancel) = &self.on_cancel {
+                on_cancel(reason);
+            }
+            let parent = self
+                .parent
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            if let Some(parent) = parent {
+                parent.record(reason);
+            }
+        }
+    }
+
+    /// Records `reason` when dropped before `disarm`.
+    pub(crate) struct CancelGuard {
+        cancellation: Option<Cancellation>,
+        reason: CancelReason,
+    }
+
+    impl CancelGuard {
+        pub(crate) fn new(cancellation: Option<Cancellation>, reason: CancelReason) -> Self {
+            Self {
+                cancellation,
+                reason,
+            }
+        }
+
+        pub(crate) fn disarm(mut self) {
+            self.cancellation = None;
+        }
+    }
+
+    impl Drop for CancelGuard {
+        fn drop(&mut self) {
+            if let Some(cancellation) = &self.cancellation {
+                cancellation.record(self.reason);
+            }
+        }
+    }
+
+    /// Records `CancelReason::ResponseBody` when `body` is dropped before its end or an error.
+    pub(crate) fn guard_body<B>(body: B, cancellation: Option<Cancellation>) -> BoxBody
+    where
+        B: HttpBody<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        match cancellation {
+            Some(cancellation) => axum::body::boxed(CancelBody {
+                inner: axum::body::boxed(body),
+                guard: Some(CancelGuard::new(
+                    Some(cancellation),
+                    CancelReason::ResponseBody,
+                )),
+            }),
+            None => axum::body::boxed(body),
+        }
+    }
+
+    struct CancelBody {
+        inner: BoxBody,
+        guard: Option<CancelGuard>,
+    }
+
+    impl CancelBody {
+        fn disarm(&mut self) {
+            if let Some(guard) = self.guard.take() {
+                guard.disarm();
+            }
+        }
+    }
+
+    impl HttpBody for CancelBody {
+        type Data = Bytes;
+        type Error = axum::Error;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            let poll = Pin::new(&mut self.inner).poll_data(cx);
+            if !matches!(poll, Poll::Pending | Poll::Ready(Some(Ok(_)))) {
+                self.disarm();
+            }
+            poll
+        }
+
+        fn poll_trailers(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            let poll = Pin::new(&mut self.inner).poll_trailers(cx);
+            if poll.is_ready() {
+                self.disarm();
+            }
+            poll
+        }
+
+        fn is_end_stream(&self) -> bool {
+            self.inner.is_end_stream()
+        }
+
+        fn size_hint(&self) -> SizeHint {
+            self.inner.size_hint()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::body::{Body as AxumBody, Bytes, HttpBody as _};
+
+    #[tokio::test]
+    async fn test_guard_body() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cancellation = Cancellation::new().on_cancel({
+            let calls = calls.to_owned();
+            move |_| {
+                calls.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        // Read to the end.
+        let body = guard_body(AxumBody::from("foo"), Some(cancellation.to_owned()));
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "foo");
+        assert!(!cancellation.is_cancelled());
+
+        // Dropped after the first chunk.
+        let (mut body_tx, body) = AxumBody::channel();
+        body_tx.send_data(Bytes::from_static(b"foo")).await.unwrap();
+        let mut body = guard_body(body, Some(cancellation.to_owned()));
+        assert_eq!(body.data().await.unwrap().unwrap(), "foo");
+        drop(body);
+        assert_eq!(cancellation.reason(), Some(CancelReason::ResponseBody));
+
+        // Only the first reason is kept.
+        drop(CancelGuard::new(
+            Some(cancellation.to_owned()),
+            CancelReason::Request,
+        ));
+        assert_eq!(cancellation.reason(), Some(CancelReason::ResponseBody));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Attempts forward to their parent until detached.
+        let parent = Cancellation::new();
+        let attempt = parent.attempt();
+        attempt.detach();
+        drop(CancelGuard::new(Some(attempt), CancelReason::Request));
+        assert_eq!(parent.reason(), None);
+        drop(CancelGuard::new(
+            Some(parent.attempt()),
+            CancelReason::Request,
+        ));
+        assert_eq!(parent.reason(), Some(CancelReason::Request));
+    }
+}
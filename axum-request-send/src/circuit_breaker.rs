@@ -0,0 +1,397 @@
+use core::time::Duration;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+
+use axum::{
+    body::Body as AxumBody, http::Request as HttpRequest, response::Response as AxumResponse,
+};
+
+use crate::{
+    error::SendError,
+    sender::{BoxFuture, RequestSender},
+    Config,
+};
+
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    /// Calls fail fast with `SendError::Unavailable`.
+    Open,
+    /// Up to `half_open_calls` probe calls are let through.
+    HalfOpen,
+}
+
+//
+/// A circuit per upstream authority.
+///
+/// A call fails when it errors, responds with 502, 503 or 504, or takes longer than
+/// `slow_call` to respond. The circuit opens after `consecutive_failures` failed calls,
+/// or when the failure rate within `window` reaches `error_rate` (after `min_calls` calls).
+/// After `open_for` it turns half-open, and closes again once all probe calls succeed.
+///
+/// Wrap the sender of an `UpstreamPool` with it to get a circuit per endpoint.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker<S> {
+    inner: S,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    consecutive_failures: usize,
+    error_rate: Option<f64>,
+    min_calls: usize,
+    window: Duration,
+    slow_call: Option<Duration>,
+    open_for: Duration,
+    half_open_calls: usize,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: usize,
+    window_start: Instant,
+    calls: usize,
+    failures: usize,
+    opened_at: Instant,
+    probes_in_flight: usize,
+    probes_succeeded: usize,
+}
+
+impl Circuit {
+    fn new(now: Instant) -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            window_start: now,
+            calls: 0,
+            failures: 0,
+            opened_at: now,
+            probes_in_flight: 0,
+            probes_succeeded: 0,
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.opened_at = now;
+        self.probes_in_flight = 0;
+        self.probes_succeeded = 0;
+    }
+}
+
+impl<S> CircuitBreaker<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            circuits: Default::default(),
+            consecutive_failures: 5,
+            error_rate: None,
+            min_calls: 20,
+            window: Duration::from_secs(10),
+            slow_call: None,
+            open_for: Duration::from_secs(30),
+            half_open_calls: 1,
+        }
+    }
+
+    /// `0` disables tripping on consecutive failures.
+    pub fn consecutive_failures(mut self, consecutive_failures: usize) -> Self {
+        self.consecutive_failures = consecutive_failures;
+        self
+    }
+
+    /// Failure rate, between `0.0` and `1.0`.
+    pub fn error_rate(mut self, error_rate: f64) -> Self {
+        self.error_rate = Some(error_rate);
+        self
+    }
+
+    pub fn min_calls(mut self, min_calls: usize) -> Self {
+        self.min_calls = min_calls;
+        self
+    }
+
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn slow_call(mut self, slow_call: Duration) -> Self {
+        self.slow_call = Some(slow_call);
+        self
+    }
+
+    pub fn open_for(mut self, open_for: Duration) -> Self {
+        self.open_for = open_for;
+        self
+    }
+
+    pub fn half_open_calls(mut self, half_open_calls: usize) -> Self {
+        self.half_open_calls = half_open_calls.max(1);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn state(&self, authority: &str) -> CircuitState {
+        let now = Instant::now();
+        match self.circuits().get(authority) {
+            Some(circuit)
+                if circuit.state == CircuitState::Open
+                    && now >= circuit.opened_at + self.open_for =>
+            {
+                CircuitState::HalfOpen
+            }
+            Some(circuit) => circuit.state,
+            None => CircuitState::Closed,
+        }
+    }
+
+    fn circuits(&self) -> MutexGuard<'_, HashMap<String, Circuit>> {
+        self.circuits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// `Ok(true)` for a half-open probe call.
+    fn acquire(&self, key: &str) -> Result<bool, SendError> {
+        let now = Instant::now();
+        let mut circuits = self.circuits();
+        let circuit = circuits
+            .entry(key.to_owned())
+            .or_insert_with(|| Circuit::new(now));
+
+        if circuit.state == CircuitState::Open && now >= circuit.opened_at + self.open_for {
+            circuit.state = CircuitState::HalfOpen;
+        }
+        match circuit.state {
+            CircuitState::Closed => Ok(false),
+            CircuitState::HalfOpen
+                if circuit.probes_in_flight + circuit.probes_succeeded < self.half_open_calls =>
+            {
+                circuit.probes_in_flight += 1;
+                Ok(true)
+            }
+            _ => Err(SendError::Unavailable(
+                format!("circuit breaker is open for {key}").into(),
+            )),
+        }
+    }
+
+    fn record(&self, key: &str, probe: bool, failed: bool) {
+        let now = Instant::now();
+        let mut circuits = self.circuits();
+        let circuit = match circuits.get_mut(key) {
+            Some(x) => x,
+            None => return,
+        };
+
+        match circuit.state {
+            CircuitState::HalfOpen if probe => {
+                circuit.probes_in_flight = circuit.probes_in_flight.saturating_sub(1);
+                if failed {
+                    circuit.open(now);
+                } else {
+                    circuit.probes_succeeded += 1;
+                    if circuit.probes_succeeded >= self.half_open_calls {
+                        *circuit = Circuit::new(now);
+                    }
+                }
+            }
+            CircuitState::Closed => {
+                if now.saturating_duration_since(circuit.window_start) >= self.window {
+                    circuit.window_start = now;
+                    circuit.calls = 0;
+                    circuit.failures = 0;
+                }
+                circuit.calls += 1;
+                if failed {
+                    circuit.failures += 1;
+                    circuit.consecutive_failures += 1;
+                } else {
+                    circuit.consecutive_failures = 0;
+                }
+
+                let too_many_failures = self.consecutive_failures > 0
+                    && circuit.consecutive_failures >= self.consecutive_failures;
+                let error_rate_exceeded = match self.error_rate {
+                    Some(error_rate) if circuit.calls >= self.min_calls.max(1) => {
+                        circuit.failures as f64 / circuit.calls as f64 >= error_rate
+                    }
+                    _ => false,
+                };
+                if too_many_failures || error_rate_exceeded {
+                    circuit.open(now);
+                }
+            }
+            // Calls started before the circuit opened.
+            _ => {}
+        }
+    }
+}
+
+impl<S> RequestSender for CircuitBreaker<S>
+where
+    S: RequestSender,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            let key = http_request
+                .uri()
+                .authority()
+                .map(|x| x.as_str().to_owned())
+                .unwrap_or_default();
+            let probe = self.acquire(&key)?;
+            let mut permit = Permit {
+                breaker: self,
+                key: &key,
+                probe,
+            };
+
+            let started_at = Instant::now();
+            let result = self
+                .inner
+                .send_with_config(http_request, config)
+                .await
+                .map_err(Into::into);
+
+            let failed = match &result {
+                Ok(response) => {
+                    matches!(response.status().as_u16(), 502..=504)
+                        || self
+                            .slow_call
+                            .map(|x| started_at.elapsed() > x)
+                            .unwrap_or(false)
+                }
+                Err(_) => true,
+            };
+            self.record(&key, permit.probe, failed);
+            permit.probe = false;
+
+            result
+        })
+    }
+}
+
+/// Gives the probe slot back when the call is cancelled.
+struct Permit<'a, S> {
+    breaker: &'a CircuitBreaker<S>,
+    key: &'a str,
+    probe: bool,
+}
+
+impl<S> Drop for Permit<'_, S> {
+    fn drop(&mut self) {
+        if self.probe {
+            if let Some(circuit) = self.breaker.circuits().get_mut(self.key) {
+                circuit.probes_in_flight = circuit.probes_in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::http::StatusCode;
+
+    /// Responds with `502` while `failing` is set.
+    struct Backend {
+        failing: AtomicBool,
+    }
+
+    impl RequestSender for Backend {
+        type Error = SendError;
+
+        fn send_with_config<'a>(
+            &'a self,
+            _http_request: HttpRequest<AxumBody>,
+            _config: &'a Config,
+        ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+            Box::pin(async move {
+                let mut response = AxumResponse::new(axum::body::boxed(AxumBody::empty()));
+                if self.failing.load(Ordering::SeqCst) {
+                    *response.status_mut() = StatusCode::BAD_GATEWAY;
+                }
+                Ok(response)
+            })
+        }
+    }
+
+    async fn send_to(
+        breaker: &CircuitBreaker<Backend>,
+        uri: &str,
+    ) -> Result<StatusCode, SendError> {
+        let request = HttpRequest::builder()
+            .uri(uri)
+            .body(AxumBody::empty())
+            .unwrap();
+        breaker.send(request).await.map(|x| x.status())
+    }
+
+    #[tokio::test]
+    async fn test_consecutive_failures() {
+        let breaker = CircuitBreaker::new(Backend {
+            failing: AtomicBool::new(true),
+        })
+        .consecutive_failures(3)
+        .open_for(Duration::from_millis(50))
+        .half_open_calls(2);
+
+        for _ in 0..3 {
+            assert_eq!(
+                send_to(&breaker, "http://a.internal/").await.unwrap(),
+                StatusCode::BAD_GATEWAY
+            );
+        }
+        assert_eq!(breaker.state("a.internal"), CircuitState::Open);
+        let err = send_to(&breaker, "http://a.internal/").await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            send_to(&breaker, "http://b.internal/").await.unwrap(),
+            StatusCode::BAD_GATEWAY
+        );
+
+        // A failed probe opens the circuit again.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(breaker.state("a.internal"), CircuitState::HalfOpen);
+        send_to(&breaker, "http://a.internal/").await.unwrap();
+        assert_eq!(breaker.state("a.internal"), CircuitState::Open);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        breaker.inner().failing.store(false, Ordering::SeqCst);
+        send_to(&breaker, "http://a.internal/").await.unwrap();
+        assert_eq!(breaker.state("a.internal"), CircuitState::HalfOpen);
+        send_to(&breaker, "http://a.internal/").await.unwrap();
+        assert_eq!(breaker.state("a.internal"), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_error_rate() {
+        let breaker = CircuitBreaker::new(Backend {
+            failing: AtomicBool::new(false),
+        })
+        .consecutive_failures(0)
+        .error_rate(0.5)
+        .min_calls(4);
+
+        for i in 0..4 {
+            breaker.inner().failing.store(i % 2 == 1, Ordering::SeqCst);
+            send_to(&breaker, "http://a.internal/").await.unwrap();
+        }
+        assert_eq!(breaker.state("a.internal"), CircuitState::Open);
+        assert!(send_to(&breaker, "http://a.internal/").await.is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use crate::forwarded::ForwardedMode;
+
+//
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Remove `Connection`, `Keep-Alive`, `Transfer-Encoding`, ... and the headers
+    /// named in `Connection` from the request and the response.
+    pub strip_hop_by_hop_headers: bool,
+    /// Add or extend `Forwarded` and `X-Forwarded-For/Proto/Host`.
+    pub forwarded: Option<ForwardedMode>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            strip_hop_by_hop_headers: true,
+            forwarded: None,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+use axum::{
+    body::{Bytes, Full},
+    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    response::{IntoResponse, Response as AxumResponse},
+};
+
+use crate::sender::BoxError;
+
+//
+#[derive(Debug)]
+pub enum SendError {
+    Connect(BoxError),
+    Timeout(BoxError),
+    Tls(BoxError),
+    InvalidUri(BoxError),
+    BodyStream(BoxError),
+    Protocol(BoxError),
+    /// No upstream is available to take the request.
+    Unavailable(BoxError),
+    /// The request body is larger than allowed, see `BodyLimit`.
+    PayloadTooLarge(BoxError),
+    Other(BoxError),
+}
+
+impl SendError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Timeout(_))
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::Connect(_) => "upstream connect failed",
+            Self::Timeout(_) => "upstream timed out",
+            Self::Tls(_) => "upstream tls failed",
+            Self::InvalidUri(_) => "invalid upstream uri",
+            Self::BodyStream(_) => "body stream failed",
+            Self::Protocol(_) => "upstream protocol error",
+            Self::Unavailable(_) => "upstream unavailable",
+            Self::PayloadTooLarge(_) => "request body too large",
+            Self::Other(_) => "upstream request failed",
+        }
+    }
+
+    fn source_ref(&self) -> &BoxError {
+        match self {
+            Self::Connect(err)
+            | Self::Timeout(err)
+            | Self::Tls(err)
+            | Self::InvalidUri(err)
+            | Self::BodyStream(err)
+            | Self::Protocol(err)
+            | Self::Unavailable(err)
+            | Self::PayloadTooLarge(err)
+            | Self::Other(err) => err,
+        }
+    }
+}
+
+impl core::fmt::Display for SendError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}: {}", self.description(), self.source_ref())
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source_ref().as_ref())
+    }
+}
+
+/// Renders only the status code, the upstream error is not exposed to the client.
+impl IntoResponse for SendError {
+    fn into_response(self) -> AxumResponse {
+        self.status_code().into_response()
+    }
+}
+
+//
+/// Renders a `SendError` as an `application/problem+json` body (RFC 9457).
+#[derive(Debug)]
+pub struct ProblemJson(pub SendError);
+
+impl IntoResponse for ProblemJson {
+    fn into_response(self) -> AxumResponse {
+        let status = self.0.status_code();
+        let body = format!(
+            r#"{{"type":"about:blank","title":"{}","status":{},"detail":"{}"}}"#,
+            status.canonical_reason().unwrap_or_default(),
+            status.as_u16(),
+            self.0.description()
+        );
+
+        let mut response = (status, Full::new(Bytes::from(body))).into_response();
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+    }
+}
+
+//
+impl From<IoError> for SendError {
+    fn from(err: IoError) -> Self {
+        match err.kind() {
+            IoErrorKind::TimedOut => Self::Timeout(err.into()),
+            IoErrorKind::ConnectionRefused
+            | IoErrorKind::ConnectionReset
+            | IoErrorKind::ConnectionAborted
+            | IoErrorKind::NotConnected
+            | IoErrorKind::AddrNotAvailable => Self::Connect(err.into()),
+            _ => Self::BodyStream(err.into()),
+        }
+    }
+}
+
+impl From<axum::http::Error> for SendError {
+    fn from(err: axum::http::Error) -> Self {
+        Self::InvalidUri(err.into())
+    }
+}
+
+impl From<axum::http::header::InvalidHeaderValue> for SendError {
+    fn from(err: axum::http::header::InvalidHeaderValue) -> Self {
+        Self::Other(err.into())
+    }
+}
+
+impl From<axum::http::uri::InvalidUri> for SendError {
+    fn from(err: axum::http::uri::InvalidUri) -> Self {
+        Self::InvalidUri(err.into())
+    }
+}
+
+#[cfg(feature = "impl_reqwest")]
+impl From<reqwest::Error> for SendError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout(err.into())
+        } else if err.is_connect() {
+            Self::Connect(err.into())
+        } else if err.is_builder() {
+            Self::InvalidUri(err.into())
+        } else if err.is_body() || err.is_decode() {
+            Self::BodyStream(err.into())
+        } else if err.is_request() {
+            Self::Protocol(err.into())
+        } else {
+            Self::Other(err.into())
+        }
+    }
+}
+
+#[cfg(feature = "impl_isahc")]
+impl From<isahc::Error> for SendError {
+    fn from(err: isahc::Error) -> Self {
+        use isahc::error::ErrorKind;
+
+        match err.kind() {
+            ErrorKind::Timeout => Self::Timeout(err.into()),
+            ErrorKind::ConnectionFailed | ErrorKind::NameResolution => Self::Connect(err.into()),
+            ErrorKind::BadClientCertificate
+            | ErrorKind::BadServerCertificate
+            | ErrorKind::TlsEngine => Self::Tls(err.into()),
+            ErrorKind::InvalidRequest => Self::InvalidUri(err.into()),
+            ErrorKind::Io | ErrorKind::RequestBodyNotRewindable => Self::BodyStream(err.into()),
+            ErrorKind::ProtocolViolation | ErrorKind::InvalidContentEncoding => {
+                Self::Protocol(err.into())
+            }
+            _ => Self::Other(err.into()),
+        }
+    }
+}
+
+#[cfg(feature = "impl_hyper")]
+impl From<hyper::Error> for SendError {
+    fn from(err: hyper::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout(err.into())
+        } else if err.is_connect() {
+            Self::Connect(err.into())
+        } else if err.is_user() {
+            Self::InvalidUri(err.into())
+        } else if err.is_body_write_aborted() || err.is_incomplete_message() {
+            Self::BodyStream(err.into())
+        } else if err.is_parse() || err.is_closed() {
+            Self::Protocol(err.into())
+        } else {
+            Self::Other(err.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_into_response() {
+        let err = SendError::from(IoError::from(IoErrorKind::TimedOut));
+        assert!(err.is_timeout());
+        assert_eq!(err.into_response().status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let err = SendError::from(IoError::from(IoErrorKind::ConnectionRefused));
+        assert!(matches!(err, SendError::Connect(_)));
+        let response = ProblemJson(err).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            r#"{"type":"about:blank","title":"Bad Gateway","status":502,"detail":"upstream connect failed"}"#
+        );
+    }
+}
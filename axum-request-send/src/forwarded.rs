@@ -0,0 +1,237 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, OriginalUri},
+    http::{header::HOST, HeaderMap, HeaderValue, Request as HttpRequest},
+};
+
+//
+pub const FORWARDED: &str = "forwarded";
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+pub const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+pub const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+//
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardedMode {
+    /// Extend the incoming `Forwarded` / `X-Forwarded-*` headers.
+    Append,
+    /// Discard the incoming `Forwarded` / `X-Forwarded-*` headers.
+    Replace,
+    /// `Append` when the peer is one of these proxies, otherwise `Replace`.
+    TrustedProxies(Vec<IpAddr>),
+}
+
+//
+pub fn set_forwarded_headers<B>(http_request: &mut HttpRequest<B>, mode: &ForwardedMode) {
+    let peer_ip = http_request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    // The request URI may already point to the upstream, only the incoming one is used for the scheme.
+    let original_uri = http_request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|OriginalUri(uri)| uri);
+    let proto = original_uri
+        .and_then(|uri| uri.scheme_str())
+        .unwrap_or("http")
+        .to_owned();
+    // HTTP/2 requests may only carry `:authority`.
+    let host = http_request
+        .headers()
+        .get(HOST)
+        .and_then(|x| x.to_str().ok())
+        .or_else(|| {
+            original_uri
+                .unwrap_or_else(|| http_request.uri())
+                .authority()
+                .map(|x| x.as_str())
+        })
+        .map(ToOwned::to_owned);
+
+    let append = match mode {
+        ForwardedMode::Append => true,
+        ForwardedMode::Replace => false,
+        ForwardedMode::TrustedProxies(proxies) => {
+            peer_ip.map(|ip| proxies.contains(&ip)).unwrap_or(false)
+        }
+    };
+
+    let headers = http_request.headers_mut();
+    if !append {
+        for name in [
+            FORWARDED,
+            X_FORWARDED_FOR,
+            X_FORWARDED_PROTO,
+            X_FORWARDED_HOST,
+        ] {
+            headers.remove(name);
+        }
+    }
+
+    //
+    let mut forwarded = vec![format!(
+        "for={}",
+        peer_ip
+            .map(forwarded_node)
+            .unwrap_or_else(|| "unknown".to_owned())
+    )];
+    if let Some(host) = &host {
+        forwarded.push(format!("host={}", forwarded_value(host)));
+    }
+    forwarded.push(format!("proto={proto}"));
+    append_header(headers, FORWARDED, &forwarded.join(";"));
+
+    //
+    if let Some(peer_ip) = peer_ip {
+        append_header(headers, X_FORWARDED_FOR, &peer_ip.to_string());
+    }
+    if !headers.contains_key(X_FORWARDED_PROTO) {
+        if let Ok(value) = HeaderValue::from_str(&proto) {
+            headers.insert(X_FORWARDED_PROTO, value);
+        }
+    }
+    if let Some(host) = host {
+        if !headers.contains_key(X_FORWARDED_HOST) {
+            if let Ok(value) = HeaderValue::from_str(&host) {
+                headers.insert(X_FORWARDED_HOST, value);
+            }
+        }
+    }
+}
+
+// Ref https://www.rfc-editor.org/rfc/rfc7239#section-6
+fn forwarded_node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{ip}]\""),
+    }
+}
+
+fn forwarded_value(value: &str) -> String {
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+    {
+        value.to_owned()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn append_header(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    // Repeated field lines are one comma-separated list.
+    let mut values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    values.push(value);
+    let value = values.join(", ");
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::body::Body;
+
+    fn make_request(peer: &str) -> HttpRequest<Body> {
+        let mut request = HttpRequest::builder()
+            .uri("http://127.0.0.1:8080/foo")
+            .header("host", "example.com")
+            .header("forwarded", "for=192.0.2.60;proto=https")
+            .header("x-forwarded-for", "192.0.2.60")
+            .header("x-forwarded-proto", "https")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        request
+    }
+
+    #[test]
+    fn test_append() {
+        let mut request = make_request("10.0.0.1:50000");
+        set_forwarded_headers(&mut request, &ForwardedMode::Append);
+
+        let headers = request.headers();
+        assert_eq!(
+            headers.get(FORWARDED).unwrap(),
+            "for=192.0.2.60;proto=https, for=10.0.0.1;host=example.com;proto=http"
+        );
+        assert_eq!(
+            headers.get(X_FORWARDED_FOR).unwrap(),
+            "192.0.2.60, 10.0.0.1"
+        );
+        assert_eq!(headers.get(X_FORWARDED_PROTO).unwrap(), "https");
+        assert_eq!(headers.get(X_FORWARDED_HOST).unwrap(), "example.com");
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut request = make_request("[2001:db8::1]:50000");
+        set_forwarded_headers(&mut request, &ForwardedMode::Replace);
+
+        let headers = request.headers();
+        assert_eq!(
+            headers.get(FORWARDED).unwrap(),
+            "for=\"[2001:db8::1]\";host=example.com;proto=http"
+        );
+        assert_eq!(headers.get(X_FORWARDED_FOR).unwrap(), "2001:db8::1");
+        assert_eq!(headers.get(X_FORWARDED_PROTO).unwrap(), "http");
+        assert_eq!(headers.get(X_FORWARDED_HOST).unwrap(), "example.com");
+    }
+
+    #[test]
+    fn test_incoming_request() {
+        // The URI was rewritten to the upstream, the client used h2 with only `:authority`.
+        let mut request = HttpRequest::builder()
+            .uri("https://backend:8443/foo")
+            .header("x-forwarded-for", "192.0.2.60")
+            .header("x-forwarded-for", "192.0.2.61, 192.0.2.62")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(OriginalUri("http://example.com/foo".parse().unwrap()));
+        request
+            .extensions_mut()
+            .insert(ConnectInfo("10.0.0.1:50000".parse::<SocketAddr>().unwrap()));
+        set_forwarded_headers(&mut request, &ForwardedMode::Append);
+
+        let headers = request.headers();
+        assert_eq!(
+            headers.get(FORWARDED).unwrap(),
+            "for=10.0.0.1;host=example.com;proto=http"
+        );
+        assert_eq!(
+            headers.get(X_FORWARDED_FOR).unwrap(),
+            "192.0.2.60, 192.0.2.61, 192.0.2.62, 10.0.0.1"
+        );
+        assert_eq!(headers.get(X_FORWARDED_PROTO).unwrap(), "http");
+        assert_eq!(headers.get(X_FORWARDED_HOST).unwrap(), "example.com");
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let mode = ForwardedMode::TrustedProxies(vec!["10.0.0.1".parse().unwrap()]);
+
+        let mut request = make_request("10.0.0.1:50000");
+        set_forwarded_headers(&mut request, &mode);
+        assert_eq!(
+            request.headers().get(X_FORWARDED_FOR).unwrap(),
+            "192.0.2.60, 10.0.0.1"
+        );
+
+        let mut request = make_request("10.0.0.2:50000");
+        set_forwarded_headers(&mut request, &mode);
+        assert_eq!(request.headers().get(X_FORWARDED_FOR).unwrap(), "10.0.0.2");
+    }
+}
@@ -0,0 +1,89 @@
+use axum::http::{
+    header::{CONTENT_LENGTH, TRANSFER_ENCODING},
+    HeaderMap, Response as HttpResponse, StatusCode, Version,
+};
+
+//
+/// Fixes up a proxied response for the downstream connection.
+///
+/// The version is the downstream one, the server frames the re-streamed body itself.
+/// `Transfer-Encoding` is dropped, `Content-Length` is kept only when it is a single valid
+/// length that the upstream framed the body with.
+pub fn normalize_response_framing<B>(
+    http_response: &mut HttpResponse<B>,
+    downstream_version: Version,
+) {
+    *http_response.version_mut() = downstream_version;
+
+    let status = http_response.status();
+    let headers = http_response.headers_mut();
+    let chunked = headers.remove(TRANSFER_ENCODING).is_some();
+    let content_length_is_safe = !chunked
+        && !status.is_informational()
+        && status != StatusCode::NO_CONTENT
+        && content_length(headers).is_some();
+    if !content_length_is_safe {
+        headers.remove(CONTENT_LENGTH);
+    }
+}
+
+/// `None` when missing, invalid or conflicting.
+pub fn content_length(headers: &HeaderMap) -> Option<u64> {
+    let mut length = None;
+    for value in headers.get_all(CONTENT_LENGTH) {
+        for x in value.to_str().ok()?.split(',') {
+            let x = x.trim().parse::<u64>().ok()?;
+            match length {
+                Some(length) if length != x => return None,
+                _ => length = Some(x),
+            }
+        }
+    }
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_response_framing() {
+        let normalize = |status: StatusCode, headers: &[(&'static str, &str)]| {
+            let mut response = HttpResponse::new(());
+            *response.status_mut() = status;
+            *response.version_mut() = Version::HTTP_2;
+            for (k, v) in headers {
+                response.headers_mut().append(*k, v.parse().unwrap());
+            }
+            normalize_response_framing(&mut response, Version::HTTP_11);
+            assert_eq!(response.version(), Version::HTTP_11);
+            response.headers().get(CONTENT_LENGTH).cloned()
+        };
+
+        assert_eq!(
+            normalize(StatusCode::OK, &[("content-length", "3")]).unwrap(),
+            "3"
+        );
+        assert!(normalize(
+            StatusCode::OK,
+            &[("content-length", "3"), ("transfer-encoding", "chunked")]
+        )
+        .is_none());
+        assert!(normalize(StatusCode::OK, &[("content-length", "3, 4")]).is_none());
+        assert!(normalize(StatusCode::NO_CONTENT, &[("content-length", "0")]).is_none());
+    }
+
+    #[test]
+    fn test_content_length() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(content_length(&headers), None);
+        headers.insert(CONTENT_LENGTH, "42, 42".parse().unwrap());
+        assert_eq!(content_length(&headers), Some(42));
+        headers.append(CONTENT_LENGTH, "42".parse().unwrap());
+        assert_eq!(content_length(&headers), Some(42));
+        headers.append(CONTENT_LENGTH, "43".parse().unwrap());
+        assert_eq!(content_length(&headers), None);
+        headers.insert(CONTENT_LENGTH, "-1".parse().unwrap());
+        assert_eq!(content_length(&headers), None);
+    }
+}
@@ -0,0 +1,234 @@
+use core::time::Duration;
+use std::sync::Arc;
+
+use axum::{
+    body::Body as AxumBody,
+    http::{header::HOST, HeaderValue, Request as HttpRequest, StatusCode, Uri},
+};
+use futures_util::future::join_all;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    sender::RequestSender,
+    upstream_pool::{Endpoint, UpstreamPool},
+    Config,
+};
+
+//
+/// Active health probe, spawned with `UpstreamPool::spawn_health_check`.
+///
+/// Every `interval`, a `GET path` is sent to each endpoint. An endpoint becomes unhealthy
+/// after `unhealthy_threshold` consecutive failed probes, and healthy again after
+/// `healthy_threshold` consecutive successful ones.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    path: String,
+    interval: Duration,
+    timeout: Duration,
+    expected_status: Vec<StatusCode>,
+    healthy_threshold: usize,
+    unhealthy_threshold: usize,
+}
+
+impl HealthCheck {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            expected_status: vec![],
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Empty (the default) accepts any `2xx`.
+    pub fn expected_status(mut self, expected_status: Vec<StatusCode>) -> Self {
+        self.expected_status = expected_status;
+        self
+    }
+
+    pub fn healthy_threshold(mut self, healthy_threshold: usize) -> Self {
+        self.healthy_threshold = healthy_threshold.max(1);
+        self
+    }
+
+    pub fn unhealthy_threshold(mut self, unhealthy_threshold: usize) -> Self {
+        self.unhealthy_threshold = unhealthy_threshold.max(1);
+        self
+    }
+
+    async fn probe<S>(&self, sender: &S, endpoint: &Endpoint) -> bool
+    where
+        S: RequestSender,
+    {
+        let http_request = match self.request(endpoint) {
+            Some(x) => x,
+            None => return false,
+        };
+        let config = Config::default();
+        match tokio::time::timeout(self.timeout, sender.send_with_config(http_request, &config))
+            .await
+        {
+            Ok(Ok(response)) => {
+                if self.expected_status.is_empty() {
+                    response.status().is_success()
+                } else {
+                    self.expected_status.contains(&response.status())
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn request(&self, endpoint: &Endpoint) -> Option<HttpRequest<AxumBody>> {
+        let uri = Uri::builder()
+            .scheme(endpoint.scheme().to_owned())
+            .authority(endpoint.authority().to_owned())
+            .path_and_query(self.path.as_str())
+            .build()
+            .ok()?;
+        let mut http_request = HttpRequest::new(AxumBody::empty());
+        *http_request.uri_mut() = uri;
+        http_request.headers_mut().insert(
+            HOST,
+            HeaderValue::from_str(endpoint.authority().as_str()).ok()?,
+        );
+        Some(http_request)
+    }
+}
+
+impl<S> UpstreamPool<S> {
+    /// Probes every endpoint in the background, with the same sender that serves requests.
+    ///
+    /// The task stops once every clone of the pool is dropped, or when the handle is aborted.
+    pub fn spawn_health_check(&self, health_check: HealthCheck) -> JoinHandle<()>
+    where
+        S: RequestSender + Clone + 'static,
+    {
+        let sender = self.inner().to_owned();
+        let endpoints = Arc::downgrade(&self.endpoints);
+
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(health_check.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let endpoints = match endpoints.upgrade() {
+                    Some(x) => x,
+                    None => break,
+                };
+
+                let results = join_all(
+                    endpoints
+                        .iter()
+                        .map(|endpoint| health_check.probe(&sender, endpoint)),
+                )
+                .await;
+                for (endpoint, success) in endpoints.iter().zip(results) {
+                    endpoint.record_probe(
+                        success,
+                        health_check.healthy_threshold,
+                        health_check.unhealthy_threshold,
+                    );
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::response::Response as AxumResponse;
+
+    use crate::{error::SendError, sender::BoxFuture};
+
+    /// `/healthz` of `b.internal` fails while `b_down` is set.
+    #[derive(Clone)]
+    struct Backend {
+        b_down: Arc<AtomicBool>,
+    }
+
+    impl RequestSender for Backend {
+        type Error = SendError;
+
+        fn send_with_config<'a>(
+            &'a self,
+            http_request: HttpRequest<AxumBody>,
+            _config: &'a Config,
+        ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+            Box::pin(async move {
+                let authority = http_request.uri().authority().unwrap().to_string();
+                let mut response =
+                    AxumResponse::new(axum::body::boxed(AxumBody::from(authority.to_owned())));
+                if http_request.uri().path() == "/healthz"
+                    && authority == "b.internal"
+                    && self.b_down.load(Ordering::SeqCst)
+                {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                }
+                Ok(response)
+            })
+        }
+    }
+
+    /// Polls `f` instead of sleeping for a fixed number of probes.
+    async fn wait_until(f: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !f() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("state not reached");
+    }
+
+    #[tokio::test]
+    async fn test_spawn_health_check() {
+        let b_down = Arc::new(AtomicBool::new(true));
+        let pool = UpstreamPool::new(
+            Backend {
+                b_down: b_down.to_owned(),
+            },
+            vec![
+                "http://a.internal".parse().unwrap(),
+                "http://b.internal".parse().unwrap(),
+            ],
+        );
+        let handle = pool.spawn_health_check(
+            HealthCheck::new("/healthz")
+                .interval(Duration::from_millis(10))
+                .healthy_threshold(2)
+                .unhealthy_threshold(2),
+        );
+
+        wait_until(|| !pool.endpoints()[1].is_healthy()).await;
+        assert!(pool.endpoints()[0].is_healthy());
+        for _ in 0..4 {
+            assert_eq!(pool.pick(None).unwrap().authority(), "a.internal");
+        }
+
+        b_down.store(false, Ordering::SeqCst);
+        wait_until(|| pool.endpoints()[1].is_healthy()).await;
+
+        drop(pool);
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use axum::http::{
+    header::{CONNECTION, TE},
+    HeaderMap, HeaderName, HeaderValue,
+};
+
+// Ref https://www.rfc-editor.org/rfc/rfc9110#section-7.6.1
+pub const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authorization",
+    "proxy-authenticate",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+//
+pub fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let connection_headers = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .filter_map(|x| HeaderName::from_bytes(x.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+
+    for name in connection_headers {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
+    }
+}
+
+/// `TE: trailers`, required by gRPC.
+pub fn accepts_trailers(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(TE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(','))
+        .any(|x| x.trim().eq_ignore_ascii_case("trailers"))
+}
+
+/// Like `remove_hop_by_hop_headers`, but keeps `TE: trailers`,
+/// for the senders that carry trailers end to end.
+pub fn remove_hop_by_hop_headers_keep_te_trailers(headers: &mut HeaderMap) {
+    let te_trailers = accepts_trailers(headers);
+    remove_hop_by_hop_headers(headers);
+    if te_trailers {
+        headers.insert(TE, HeaderValue::from_static("trailers"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("connection", "keep-alive, X-Foo ,upgrade".parse().unwrap());
+        headers.insert("keep-alive", "timeout=5".parse().unwrap());
+        headers.insert("transfer-encoding", "chunked".parse().unwrap());
+        headers.insert("te", "trailers".parse().unwrap());
+        headers.insert("upgrade", "websocket".parse().unwrap());
+        headers.insert("proxy-authorization", "Basic Zm9vOmJhcg==".parse().unwrap());
+        headers.insert("x-foo", "1".parse().unwrap());
+        headers.insert("x-bar", "2".parse().unwrap());
+        headers.insert("content-type", "text/plain".parse().unwrap());
+
+        remove_hop_by_hop_headers(&mut headers);
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get("x-bar").unwrap(), "2");
+        assert_eq!(headers.get("content-type").unwrap(), "text/plain");
+    }
+
+    #[test]
+    fn test_remove_hop_by_hop_headers_keep_te_trailers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("te", "deflate, Trailers".parse().unwrap());
+        headers.insert("content-type", "application/grpc".parse().unwrap());
+
+        remove_hop_by_hop_headers_keep_te_trailers(&mut headers);
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get("te").unwrap(), "trailers");
+    }
+}
@@ -0,0 +1,578 @@
+use axum::{
+    body::Body as AxumBody,
+    http::{Request as HttpRequest, StatusCode},
+    response::Response as AxumResponse,
+};
+use hyper::{client::connect::Connect, upgrade::OnUpgrade, Client, Error as HyperError};
+
+#[cfg(unix)]
+use crate::unix_socket::{set_socket_authority, UnixSocket};
+use crate::{
+    cancellation::{guard_body, CancelGuard, CancelReason, Cancellation},
+    forwarded::set_forwarded_headers,
+    framing::normalize_response_framing,
+    hop_by_hop::{remove_hop_by_hop_headers, remove_hop_by_hop_headers_keep_te_trailers},
+    sender::{BoxFuture, RequestSender},
+    upgrade::{is_upgrade_request, remove_hop_by_hop_headers_keep_upgrade, spawn_tunnel},
+    Config,
+};
+
+//
+pub async fn send<C>(
+    client: &Client<C, AxumBody>,
+    http_request: HttpRequest<AxumBody>,
+) -> Result<AxumResponse, HyperError>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    send_with_config(client, http_request, &Config::default()).await
+}
+
+pub async fn send_with_config<C>(
+    client: &Client<C, AxumBody>,
+    mut http_request: HttpRequest<AxumBody>,
+    config: &Config,
+) -> Result<AxumResponse, HyperError>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    if let Some(mode) = &config.forwarded {
+        set_forwarded_headers(&mut http_request, mode);
+    }
+    // Needs a client built on `UnixConnector`.
+    #[cfg(unix)]
+    if let Some(socket) = http_request.extensions_mut().remove::<UnixSocket>() {
+        set_socket_authority(&mut http_request, &socket);
+    }
+    // hyper rejects an HTTP/2 request on an HTTP/1 connection, and ignores the version on h2 ones,
+    // so an `http2_only` (or ALPN h2) client still talks h2 to the upstream.
+    let downstream_version = core::mem::take(http_request.version_mut());
+    let cancellation = http_request.extensions().get::<Cancellation>().cloned();
+    let upgrade = is_upgrade_request(&http_request);
+    let downstream_upgrade = if upgrade {
+        http_request.extensions_mut().remove::<OnUpgrade>()
+    } else {
+        None
+    };
+    if config.strip_hop_by_hop_headers {
+        if upgrade {
+            remove_hop_by_hop_headers_keep_upgrade(http_request.headers_mut());
+        } else {
+            remove_hop_by_hop_headers_keep_te_trailers(http_request.headers_mut());
+        }
+    }
+
+    // The body is passed through as-is, in both directions, trailers included.
+    // Build the client with `http2_only(true)` to talk h2c to gRPC upstreams.
+    // Dropping the future aborts the upstream request, e.g. when the client disconnects.
+    let cancel_guard = CancelGuard::new(cancellation.to_owned(), CancelReason::Request);
+    let hyper_response = client.request(http_request).await;
+    cancel_guard.disarm();
+    let mut hyper_response = hyper_response?;
+    let switching = upgrade && hyper_response.status() == StatusCode::SWITCHING_PROTOCOLS;
+    normalize_response_framing(&mut hyper_response, downstream_version);
+    if config.strip_hop_by_hop_headers {
+        if switching {
+            remove_hop_by_hop_headers_keep_upgrade(hyper_response.headers_mut());
+        } else {
+            remove_hop_by_hop_headers(hyper_response.headers_mut());
+        }
+    }
+    if switching {
+        // The client connection is upgraded by the server once this 101 is written.
+        if let Some(downstream_upgrade) = downstream_upgrade {
+            spawn_tunnel(downstream_upgrade, hyper::upgrade::on(&mut hyper_response));
+        }
+    }
+
+    // A 101 body is empty, it may be dropped unread.
+    let cancellation = cancellation.filter(|_| !switching);
+    Ok(hyper_response.map(|body| guard_body(body, cancellation)))
+}
+
+//
+impl<C> RequestSender for Client<C, AxumBody>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    type Error = HyperError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(send_with_config(self, http_request, config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+
+    use axum::{routing::get, Router, Server};
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+        let server_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new().route("/", get(|| async { "backend" }));
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        let server_task = tokio::task::spawn(async move {
+            use axum::{body::Body, http::Request};
+
+            let client = Client::new();
+
+            let app = Router::new().route(
+                "/",
+                get(move |mut request: Request<Body>| async move {
+                    *request.uri_mut() = format!("http://{}{}", backend_listen_addr, "/")
+                        .parse()
+                        .unwrap();
+                    send(&client, request).await.unwrap()
+                }),
+            );
+
+            let server = Server::bind(&server_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("server start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        //
+        let resp = Client::new()
+            .get(format!("http://{}{}", server_listen_addr, "/").parse()?)
+            .await?;
+        assert!(resp.status().is_success());
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await?, "backend");
+
+        //
+        server_task.abort();
+        assert!(server_task.await.unwrap_err().is_cancelled());
+
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_upgrade() -> Result<(), Box<dyn std::error::Error>> {
+        use axum::http::header::{CONNECTION, UPGRADE};
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+        let server_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new().route(
+                "/echo",
+                get(|mut request: HttpRequest<AxumBody>| async move {
+                    assert_eq!(request.headers().get(UPGRADE).unwrap(), "echo");
+                    let on_upgrade = hyper::upgrade::on(&mut request);
+                    tokio::task::spawn(async move {
+                        let mut io = on_upgrade.await.unwrap();
+                        let mut buf = [0; 4];
+                        io.read_exact(&mut buf).await.unwrap();
+                        io.write_all(&buf).await.unwrap();
+                    });
+                    (
+                        StatusCode::SWITCHING_PROTOCOLS,
+                        [(CONNECTION, "upgrade"), (UPGRADE, "echo")],
+                    )
+                }),
+            );
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        let server_task = tokio::task::spawn(async move {
+            let client = Client::new();
+
+            let app = Router::new().route(
+                "/echo",
+                get(move |mut request: HttpRequest<AxumBody>| async move {
+                    *request.uri_mut() = format!("http://{}{}", backend_listen_addr, "/echo")
+                        .parse()
+                        .unwrap();
+                    send(&client, request).await.unwrap()
+                }),
+            );
+
+            let server = Server::bind(&server_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("server start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        //
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", server_listen_addr, "/echo"))
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "echo")
+            .body(hyper::Body::empty())?;
+        let mut resp = Client::new().request(request).await?;
+        assert_eq!(resp.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(resp.headers().get(UPGRADE).unwrap(), "echo");
+
+        let mut io = hyper::upgrade::on(&mut resp).await?;
+        io.write_all(b"ping").await?;
+        let mut buf = [0; 4];
+        io.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+
+        //
+        server_task.abort();
+        assert!(server_task.await.unwrap_err().is_cancelled());
+
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_grpc() -> Result<(), Box<dyn std::error::Error>> {
+        use axum::{
+            body::Bytes,
+            http::{
+                header::{CONTENT_TYPE, TE},
+                HeaderMap, Version,
+            },
+            routing::post,
+        };
+        use hyper::body::HttpBody as _;
+
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+        let server_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+
+        // A tonic-style unary call, the status is in the trailers.
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new()
+                .route(
+                    "/version",
+                    get(|request: HttpRequest<AxumBody>| async move {
+                        format!("{:?}", request.version())
+                    }),
+                )
+                .route(
+                    "/helloworld.Greeter/SayHello",
+                    post(|request: HttpRequest<AxumBody>| async move {
+                        assert_eq!(request.version(), Version::HTTP_2);
+                        assert_eq!(request.headers().get(TE).unwrap(), "trailers");
+                        let message = hyper::body::to_bytes(request.into_body()).await.unwrap();
+
+                        let (mut body_tx, body) = AxumBody::channel();
+                        tokio::task::spawn(async move {
+                            body_tx.send_data(message).await.unwrap();
+                            let mut trailers = HeaderMap::new();
+                            trailers.insert("grpc-status", "0".parse().unwrap());
+                            trailers.insert("grpc-message", "ok".parse().unwrap());
+                            body_tx.send_trailers(trailers).await.unwrap();
+                        });
+                        (
+                            [(CONTENT_TYPE, "application/grpc")],
+                            axum::body::boxed(body),
+                        )
+                    }),
+                );
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        let server_task = tokio::task::spawn(async move {
+            let client = Client::builder().http2_only(true).build_http();
+            let http1_client = Client::new();
+
+            let app = Router::new()
+                .route(
+                    "/version",
+                    get(move |mut request: HttpRequest<AxumBody>| async move {
+                        *request.uri_mut() =
+                            format!("http://{}{}", backend_listen_addr, "/version")
+                                .parse()
+                                .unwrap();
+                        send(&http1_client, request).await.unwrap()
+                    }),
+                )
+                .route(
+                    "/helloworld.Greeter/SayHello",
+                    post(move |mut request: HttpRequest<AxumBody>| async move {
+                        *request.uri_mut() = format!(
+                            "http://{}{}",
+                            backend_listen_addr, "/helloworld.Greeter/SayHello"
+                        )
+                        .parse()
+                        .unwrap();
+                        send(&client, request).await.unwrap()
+                    }),
+                );
+
+            let server = Server::bind(&server_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("server start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        //
+        let message = Bytes::from_static(b"\x00\x00\x00\x00\x07\x0a\x05world");
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri(format!(
+                "http://{}{}",
+                server_listen_addr, "/helloworld.Greeter/SayHello"
+            ))
+            .version(Version::HTTP_2)
+            .header(CONTENT_TYPE, "application/grpc")
+            .header(TE, "trailers")
+            .body(hyper::Body::from(message.to_owned()))?;
+        let resp = Client::builder()
+            .http2_only(true)
+            .build_http()
+            .request(request)
+            .await?;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.headers().get(CONTENT_TYPE).unwrap(),
+            "application/grpc"
+        );
+
+        let mut body = resp.into_body();
+        let mut data = vec![];
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk?);
+        }
+        assert_eq!(data, message);
+        let trailers = body.trailers().await?.unwrap();
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+        assert_eq!(trailers.get("grpc-message").unwrap(), "ok");
+
+        // An h2 client connection, forwarded to an HTTP/1 upstream.
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", server_listen_addr, "/version"))
+            .version(Version::HTTP_2)
+            .body(hyper::Body::empty())?;
+        let resp = Client::builder()
+            .http2_only(true)
+            .build_http()
+            .request(request)
+            .await?;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.version(), Version::HTTP_2);
+        assert_eq!(hyper::body::to_bytes(resp.into_body()).await?, "HTTP/1.1");
+
+        //
+        server_task.abort();
+        assert!(server_task.await.unwrap_err().is_cancelled());
+
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_send_unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+        use axum::http::header::HOST;
+        use hyper::server::conn::Http;
+        use tokio::net::UnixListener;
+
+        use crate::unix_socket::{UnixConnector, UnixSocket};
+
+        //
+        let path = std::env::temp_dir().join(format!(
+            "axum-request-send-{}.sock",
+            portpicker::pick_unused_port().expect("No ports free")
+        ));
+        let listener = UnixListener::bind(&path)?;
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new().route(
+                "/users",
+                get(|request: HttpRequest<AxumBody>| async move {
+                    format!(
+                        "{} {}",
+                        request.headers().get(HOST).unwrap().to_str().unwrap(),
+                        request.uri()
+                    )
+                }),
+            );
+
+            loop {
+                let (stream, _) = listener.accept().await.expect("backend accept failed");
+                let app = app.to_owned();
+                tokio::task::spawn(async move {
+                    Http::new().serve_connection(stream, app).await.ok();
+                });
+            }
+        });
+
+        //
+        let client = Client::builder().build(UnixConnector::default());
+        let request = HttpRequest::builder()
+            .uri("http://app.internal/users?id=1")
+            .extension(UnixSocket::new(&path))
+            .body(AxumBody::empty())?;
+        let resp = send(&client, request).await?;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await?,
+            "app.internal /users?id=1"
+        );
+
+        //
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_cancel() -> Result<(), Box<dyn std::error::Error>> {
+        use std::sync::{Arc, Mutex};
+
+        use axum::body::{Bytes, HttpBody as _};
+        use tokio::sync::oneshot;
+
+        use crate::cancellation::{CancelReason, Cancellation};
+
+        struct OnDrop(Option<oneshot::Sender<()>>);
+        impl Drop for OnDrop {
+            fn drop(&mut self) {
+                if let Some(tx) = self.0.take() {
+                    tx.send(()).ok();
+                }
+            }
+        }
+
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+        let (slow_dropped_tx, slow_dropped_rx) = oneshot::channel();
+        let slow_dropped_tx = Arc::new(Mutex::new(Some(slow_dropped_tx)));
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new()
+                .route(
+                    "/stream",
+                    get(|| async {
+                        let (mut body_tx, body) = AxumBody::channel();
+                        tokio::task::spawn(async move {
+                            while body_tx
+                                .send_data(Bytes::from_static(b"chunk"))
+                                .await
+                                .is_ok()
+                            {
+                                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+                            }
+                        });
+                        axum::body::boxed(body)
+                    }),
+                )
+                .route(
+                    "/slow",
+                    get(move || {
+                        let on_drop = OnDrop(slow_dropped_tx.lock().unwrap().take());
+                        async move {
+                            let _on_drop = on_drop;
+                            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+                            "slow"
+                        }
+                    }),
+                );
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        let client = Client::new();
+
+        // The response body is dropped mid-stream.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", backend_listen_addr, "/stream"))
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        let response = send(&client, request).await?;
+        let mut body = response.into_body();
+        assert_eq!(body.data().await.unwrap()?, "chunk");
+        drop(body);
+        assert_eq!(cancellation.reason(), Some(CancelReason::ResponseBody));
+
+        // The send future is dropped before the response head.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", backend_listen_addr, "/slow"))
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        assert!(tokio::time::timeout(
+            tokio::time::Duration::from_millis(100),
+            send(&client, request)
+        )
+        .await
+        .is_err());
+        assert_eq!(cancellation.reason(), Some(CancelReason::Request));
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), slow_dropped_rx).await??;
+
+        //
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
+}
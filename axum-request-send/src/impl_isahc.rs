@@ -1,21 +1,73 @@
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    sync::Arc,
+};
 
 use axum::{
-    body::{Body as AxumBody, StreamBody as AxumStreamBody},
-    http::Request as HttpRequest,
+    body::{Body as AxumBody, BoxBody, Bytes, HttpBody, StreamBody as AxumStreamBody},
+    http::{HeaderMap, Request as HttpRequest},
     response::Response as AxumResponse,
 };
-use futures_util::TryStreamExt as _;
+use bytes::BytesMut;
+use futures_util::{stream, AsyncRead, AsyncReadExt as _, Stream, TryStreamExt};
+use http_body::SizeHint;
 use isahc::{AsyncBody as IsahcAsyncBody, Error as IsahcError, HttpClient};
 
+#[cfg(unix)]
+use crate::unix_socket::UnixSocket;
+use crate::{
+    cancellation::{guard_body, CancelGuard, CancelReason, Cancellation},
+    forwarded::set_forwarded_headers,
+    framing::{content_length, normalize_response_framing},
+    hop_by_hop::remove_hop_by_hop_headers,
+    sender::{BoxFuture, RequestSender},
+    Config,
+};
+
 //
 pub async fn send(
     client: &HttpClient,
     http_request: HttpRequest<AxumBody>,
 ) -> Result<AxumResponse, IsahcError> {
+    send_with_config(client, http_request, &Config::default()).await
+}
+
+pub async fn send_with_config(
+    client: &HttpClient,
+    mut http_request: HttpRequest<AxumBody>,
+    config: &Config,
+) -> Result<AxumResponse, IsahcError> {
+    if let Some(mode) = &config.forwarded {
+        set_forwarded_headers(&mut http_request, mode);
+    }
+    if config.strip_hop_by_hop_headers {
+        remove_hop_by_hop_headers(http_request.headers_mut());
+    }
+
+    let downstream_version = http_request.version();
+    let cancellation = http_request.extensions().get::<Cancellation>().cloned();
     let isahc_request = {
-        let (parts, body) = http_request.into_parts();
-        let body = body.map_ok(|x| x.to_vec()).map_err(|err| {
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let (mut parts, body) = http_request.into_parts();
+        #[cfg(unix)]
+        if let Some(socket) = parts.extensions.remove::<UnixSocket>() {
+            use isahc::config::{Configurable as _, Dialer};
+
+            // The URI is kept as-is, its authority is sent as the `Host`.
+            let dial = HttpRequest::builder()
+                .dial(Dialer::unix_socket(socket.path()))
+                .body(())?;
+            parts.extensions.extend(dial.into_parts().0.extensions);
+        }
+        // A known length avoids a chunked upload, which some upstreams reject.
+        let length = content_length(&parts.headers).or_else(|| HttpBody::size_hint(&body).exact());
+        // `Bytes` is `AsRef<[u8]>`, chunks are read from without a copy into a `Vec`.
+        #[allow(clippy::io_other_error)]
+        let body = TryStreamExt::map_err(body, |err| {
             // Ref https://docs.rs/hyper/0.14.25/src/hyper/error.rs.html#301-313
             if let Some(cause) = err.into_cause() {
                 IoError::new(IoErrorKind::Other, cause)
@@ -23,26 +75,187 @@ pub async fn send(
                 IoError::new(IoErrorKind::Other, "Unknown".to_string())
             }
         });
-        let body = IsahcAsyncBody::from_reader(body.into_async_read());
+        let body = match length {
+            Some(0) => IsahcAsyncBody::empty(),
+            Some(length) => IsahcAsyncBody::from_reader_sized(body.into_async_read(), length),
+            None => IsahcAsyncBody::from_reader(body.into_async_read()),
+        };
         HttpRequest::from_parts(parts, body)
     };
-    let isahc_response = client.send_async(isahc_request).await?;
+    // Dropping the future aborts the upstream request, e.g. when the client disconnects.
+    // isahc notices it on the next transfer callback, `AbortableClient` aborts right away.
+    let cancel_guard = CancelGuard::new(cancellation.to_owned(), CancelReason::Request);
+    let isahc_response = client.send_async(isahc_request).await;
+    cancel_guard.disarm();
+    let isahc_response = isahc_response?;
     let http_response = {
         let mut response = AxumResponse::new(());
         *response.status_mut() = isahc_response.status();
-        *response.version_mut() = isahc_response.version();
         *response.headers_mut() = isahc_response.headers().to_owned();
+        normalize_response_framing(&mut response, downstream_version);
+        if config.strip_hop_by_hop_headers {
+            remove_hop_by_hop_headers(response.headers_mut());
+        }
 
-        let body_stream = futures_stream_reader::reader(isahc_response.into_body());
+        // Trailers are dropped, use `impl_hyper` for gRPC.
+        // Dropping the body aborts the upstream transfer.
+        let body_stream = bytes_stream(isahc_response.into_body());
 
         let body = AxumStreamBody::new(body_stream);
 
         let (parts, _) = response.into_parts();
-        AxumResponse::from_parts(parts, axum::body::boxed(body))
+        AxumResponse::from_parts(parts, guard_body(body, cancellation))
     };
     Ok(http_response)
 }
 
+/// Reads straight into a `BytesMut` and splits frozen chunks off it,
+/// the allocation is reused once the chunks are dropped.
+/// Only what was split off is zeroed again before the next read.
+fn bytes_stream<R>(reader: R) -> impl Stream<Item = Result<Bytes, IoError>> + Send + 'static
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    stream::try_unfold(
+        (reader, BytesMut::new()),
+        |(mut reader, mut buf)| async move {
+            buf.resize(CHUNK_SIZE, 0);
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            let chunk = buf.split_to(n).freeze();
+            Ok(Some((chunk, (reader, buf))))
+        },
+    )
+}
+
+//
+impl RequestSender for HttpClient {
+    type Error = IsahcError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(send_with_config(self, http_request, config))
+    }
+}
+
+//
+/// Opt-in, sends each request with its own `HttpClient`, closed once the send future
+/// or the response body is dropped, e.g. when the client disconnects.
+///
+/// A shared `HttpClient` only aborts a cancelled transfer on its next callback, so an upstream
+/// that has not sent its response head keeps running until it does. Closing the client
+/// closes its connections right away.
+///
+/// Every request pays for a new agent thread and connection, there is no connection reuse.
+/// Prefer a shared `HttpClient`, and only use this for upstreams with long response delays.
+#[derive(Clone)]
+pub struct AbortableClient {
+    build: Arc<dyn Fn() -> Result<HttpClient, IsahcError> + Send + Sync>,
+}
+
+impl core::fmt::Debug for AbortableClient {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AbortableClient").finish_non_exhaustive()
+    }
+}
+
+impl Default for AbortableClient {
+    fn default() -> Self {
+        Self::new(HttpClient::new)
+    }
+}
+
+impl AbortableClient {
+    pub fn new(build: impl Fn() -> Result<HttpClient, IsahcError> + Send + Sync + 'static) -> Self {
+        Self {
+            build: Arc::new(build),
+        }
+    }
+}
+
+impl RequestSender for AbortableClient {
+    type Error = IsahcError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            let client = ClientGuard(Some((self.build)()?));
+            let response = send_with_config(client.get(), http_request, config).await?;
+            Ok(response.map(|body| {
+                axum::body::boxed(ClientBody {
+                    inner: body,
+                    _client: client,
+                })
+            }))
+        })
+    }
+}
+
+/// Keeps the client open until the body is dropped.
+struct ClientBody {
+    inner: BoxBody,
+    _client: ClientGuard,
+}
+
+impl HttpBody for ClientBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Closes the client off the runtime, dropping it joins its agent thread.
+struct ClientGuard(Option<HttpClient>);
+
+impl ClientGuard {
+    fn get(&self) -> &HttpClient {
+        self.0.as_ref().expect("only taken on drop")
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        if let Some(client) = self.0.take() {
+            match tokio::runtime::Handle::try_current() {
+                Ok(handle) => {
+                    handle.spawn_blocking(move || drop(client));
+                }
+                Err(_) => drop(client),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,4 +323,270 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_send_sized() -> Result<(), Box<dyn std::error::Error>> {
+        use axum::{
+            body::Bytes,
+            http::header::{CONTENT_LENGTH, TRANSFER_ENCODING},
+            routing::put,
+        };
+
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new().route(
+                "/",
+                put(|request: HttpRequest<AxumBody>| async move {
+                    assert!(request.headers().get(TRANSFER_ENCODING).is_none());
+                    let content_length = request.headers().get(CONTENT_LENGTH).unwrap().to_owned();
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    format!("{}:{}", content_length.to_str().unwrap(), body.len())
+                }),
+            );
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        let client = isahc::HttpClient::new()?;
+        let uri = format!("http://{}{}", backend_listen_addr, "/");
+
+        // Length from the size hint.
+        let request = HttpRequest::builder()
+            .method("PUT")
+            .uri(&uri)
+            .body(AxumBody::from("foobar"))?;
+        let response = send(&client, request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "6:6");
+
+        // Length from the header, the body is streamed.
+        let (mut body_tx, body) = AxumBody::channel();
+        let request = HttpRequest::builder()
+            .method("PUT")
+            .uri(&uri)
+            .header(CONTENT_LENGTH, "6")
+            .body(body)?;
+        let send_task = tokio::task::spawn(async move {
+            body_tx.send_data(Bytes::from_static(b"foo")).await.unwrap();
+            body_tx.send_data(Bytes::from_static(b"bar")).await.unwrap();
+        });
+        let response = send(&client, request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "6:6");
+        send_task.await?;
+
+        //
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_send_unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+        use axum::http::header::HOST;
+        use hyper::server::conn::Http;
+        use tokio::net::UnixListener;
+
+        use crate::unix_socket::UnixSocket;
+
+        //
+        let path = std::env::temp_dir().join(format!(
+            "axum-request-send-{}.sock",
+            portpicker::pick_unused_port().expect("No ports free")
+        ));
+        let listener = UnixListener::bind(&path)?;
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new().route(
+                "/users",
+                get(|request: HttpRequest<AxumBody>| async move {
+                    format!(
+                        "{} {}",
+                        request.headers().get(HOST).unwrap().to_str().unwrap(),
+                        request.uri()
+                    )
+                }),
+            );
+
+            loop {
+                let (stream, _) = listener.accept().await.expect("backend accept failed");
+                let app = app.to_owned();
+                tokio::task::spawn(async move {
+                    Http::new().serve_connection(stream, app).await.ok();
+                });
+            }
+        });
+
+        //
+        let client = isahc::HttpClient::new()?;
+        let request = HttpRequest::builder()
+            .uri("http://app.internal/users?id=1")
+            .extension(UnixSocket::new(&path))
+            .body(AxumBody::empty())?;
+        let resp = send(&client, request).await?;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await?,
+            "app.internal /users?id=1"
+        );
+
+        //
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_cancel() -> Result<(), Box<dyn std::error::Error>> {
+        use std::sync::{Arc, Mutex};
+
+        use axum::body::{Bytes, HttpBody as _};
+        use tokio::sync::oneshot;
+
+        use crate::cancellation::{CancelReason, Cancellation};
+
+        struct OnDrop(Option<oneshot::Sender<()>>);
+        impl Drop for OnDrop {
+            fn drop(&mut self) {
+                if let Some(tx) = self.0.take() {
+                    tx.send(()).ok();
+                }
+            }
+        }
+
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+        let (stream_closed_tx, stream_closed_rx) = oneshot::channel();
+        let stream_closed_tx = Arc::new(Mutex::new(Some(stream_closed_tx)));
+        let (slow_dropped_tx, slow_dropped_rx) = oneshot::channel();
+        let slow_dropped_tx = Arc::new(Mutex::new(Some(slow_dropped_tx)));
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new()
+                .route(
+                    "/stream",
+                    get(move || {
+                        let on_drop = OnDrop(stream_closed_tx.lock().unwrap().take());
+                        async move {
+                            let (mut body_tx, body) = AxumBody::channel();
+                            tokio::task::spawn(async move {
+                                let _on_drop = on_drop;
+                                while body_tx
+                                    .send_data(Bytes::from_static(b"chunk"))
+                                    .await
+                                    .is_ok()
+                                {
+                                    tokio::time::sleep(tokio::time::Duration::from_millis(10))
+                                        .await;
+                                }
+                            });
+                            axum::body::boxed(body)
+                        }
+                    }),
+                )
+                .route(
+                    "/slow",
+                    get(move || {
+                        let on_drop = OnDrop(slow_dropped_tx.lock().unwrap().take());
+                        async move {
+                            let _on_drop = on_drop;
+                            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+                            "slow"
+                        }
+                    }),
+                );
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        let client = isahc::HttpClient::new()?;
+
+        // The response body is dropped mid-stream.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", backend_listen_addr, "/stream"))
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        let response = send(&client, request).await?;
+        let mut body = response.into_body();
+        assert_eq!(body.data().await.unwrap()?, "chunk");
+        drop(body);
+        assert_eq!(cancellation.reason(), Some(CancelReason::ResponseBody));
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), stream_closed_rx).await??;
+
+        // The send future is dropped before the response head, the client is closed.
+        let client = AbortableClient::default();
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", backend_listen_addr, "/slow"))
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        assert!(tokio::time::timeout(
+            tokio::time::Duration::from_millis(100),
+            client.send(request)
+        )
+        .await
+        .is_err());
+        assert_eq!(cancellation.reason(), Some(CancelReason::Request));
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), slow_dropped_rx).await??;
+
+        //
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_body() -> Result<(), Box<dyn std::error::Error>> {
+        // The size hint and trailers of the wrapped body are kept.
+        let body = ClientBody {
+            inner: axum::body::boxed(AxumBody::from("foo")),
+            _client: ClientGuard(None),
+        };
+        assert_eq!(body.size_hint().exact(), Some(3));
+        assert_eq!(hyper::body::to_bytes(body).await?, "foo");
+
+        let (mut body_tx, body) = AxumBody::channel();
+        body_tx.send_data(Bytes::from_static(b"foo")).await?;
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse()?);
+        body_tx.send_trailers(trailers).await?;
+        drop(body_tx);
+        let mut body = ClientBody {
+            inner: axum::body::boxed(body),
+            _client: ClientGuard(None),
+        };
+        assert_eq!(body.data().await.unwrap()?, "foo");
+        assert!(body.data().await.is_none());
+        assert_eq!(
+            body.trailers().await?.unwrap().get("grpc-status").unwrap(),
+            "0"
+        );
+
+        Ok(())
+    }
 }
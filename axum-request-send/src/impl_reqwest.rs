@@ -3,31 +3,86 @@ use axum::{
     http::Request as HttpRequest,
     response::Response as AxumResponse,
 };
-use reqwest::{Client, Error as ReqwestError, Request as ReqwestRequest};
+use reqwest::{Client, Request as ReqwestRequest};
+
+use crate::{
+    cancellation::{guard_body, CancelGuard, CancelReason, Cancellation},
+    error::SendError,
+    forwarded::set_forwarded_headers,
+    framing::normalize_response_framing,
+    hop_by_hop::remove_hop_by_hop_headers,
+    sender::{BoxFuture, RequestSender},
+    unix_socket::UnixSocket,
+    Config,
+};
 
 //
 pub async fn send(
     client: &Client,
     http_request: HttpRequest<AxumBody>,
-) -> Result<AxumResponse, ReqwestError> {
+) -> Result<AxumResponse, SendError> {
+    send_with_config(client, http_request, &Config::default()).await
+}
+
+pub async fn send_with_config(
+    client: &Client,
+    mut http_request: HttpRequest<AxumBody>,
+    config: &Config,
+) -> Result<AxumResponse, SendError> {
+    if let Some(mode) = &config.forwarded {
+        set_forwarded_headers(&mut http_request, mode);
+    }
+    if config.strip_hop_by_hop_headers {
+        remove_hop_by_hop_headers(http_request.headers_mut());
+    }
+
+    let downstream_version = http_request.version();
+    let cancellation = http_request.extensions().get::<Cancellation>().cloned();
+    if http_request.extensions().get::<UnixSocket>().is_some() {
+        // reqwest has no Unix socket support, never fall back to TCP.
+        return Err(SendError::InvalidUri(
+            "Unix socket upstreams are not supported by impl_reqwest".into(),
+        ));
+    }
     let reqwest_request = ReqwestRequest::try_from(http_request)?;
-    let reqwest_response = client.execute(reqwest_request).await?;
+    // Dropping the future aborts the upstream request, e.g. when the client disconnects.
+    let cancel_guard = CancelGuard::new(cancellation.to_owned(), CancelReason::Request);
+    let reqwest_response = client.execute(reqwest_request).await;
+    cancel_guard.disarm();
+    let reqwest_response = reqwest_response?;
     let http_response = {
         let mut response = AxumResponse::new(());
         *response.status_mut() = reqwest_response.status();
-        *response.version_mut() = reqwest_response.version();
         *response.headers_mut() = reqwest_response.headers().to_owned();
+        normalize_response_framing(&mut response, downstream_version);
+        if config.strip_hop_by_hop_headers {
+            remove_hop_by_hop_headers(response.headers_mut());
+        }
 
+        // Trailers are dropped, use `impl_hyper` for gRPC.
         let body_stream = reqwest_response.bytes_stream();
 
         let body = AxumStreamBody::new(body_stream);
 
         let (parts, _) = response.into_parts();
-        AxumResponse::from_parts(parts, axum::body::boxed(body))
+        AxumResponse::from_parts(parts, guard_body(body, cancellation))
     };
     Ok(http_response)
 }
 
+//
+impl RequestSender for Client {
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(send_with_config(self, http_request, config))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,4 +149,125 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_send_unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+        let request = HttpRequest::builder()
+            .uri("http://app.internal/")
+            .extension(UnixSocket::new("/run/app.sock"))
+            .body(AxumBody::empty())?;
+        let err = send(&reqwest::Client::new(), request).await.unwrap_err();
+        assert!(matches!(err, SendError::InvalidUri(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_cancel() -> Result<(), Box<dyn std::error::Error>> {
+        use std::sync::{Arc, Mutex};
+
+        use axum::body::{Bytes, HttpBody as _};
+        use tokio::sync::oneshot;
+
+        use crate::cancellation::{CancelReason, Cancellation};
+
+        struct OnDrop(Option<oneshot::Sender<()>>);
+        impl Drop for OnDrop {
+            fn drop(&mut self) {
+                if let Some(tx) = self.0.take() {
+                    tx.send(()).ok();
+                }
+            }
+        }
+
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+        let (stream_closed_tx, stream_closed_rx) = oneshot::channel();
+        let stream_closed_tx = Arc::new(Mutex::new(Some(stream_closed_tx)));
+        let (slow_dropped_tx, slow_dropped_rx) = oneshot::channel();
+        let slow_dropped_tx = Arc::new(Mutex::new(Some(slow_dropped_tx)));
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new()
+                .route(
+                    "/stream",
+                    get(move || {
+                        let on_drop = OnDrop(stream_closed_tx.lock().unwrap().take());
+                        async move {
+                            let (mut body_tx, body) = AxumBody::channel();
+                            tokio::task::spawn(async move {
+                                let _on_drop = on_drop;
+                                while body_tx
+                                    .send_data(Bytes::from_static(b"chunk"))
+                                    .await
+                                    .is_ok()
+                                {
+                                    tokio::time::sleep(tokio::time::Duration::from_millis(10))
+                                        .await;
+                                }
+                            });
+                            axum::body::boxed(body)
+                        }
+                    }),
+                )
+                .route(
+                    "/slow",
+                    get(move || {
+                        let on_drop = OnDrop(slow_dropped_tx.lock().unwrap().take());
+                        async move {
+                            let _on_drop = on_drop;
+                            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+                            "slow"
+                        }
+                    }),
+                );
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        let client = reqwest::Client::new();
+
+        // The response body is dropped mid-stream.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", backend_listen_addr, "/stream"))
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        let response = send(&client, request).await?;
+        let mut body = response.into_body();
+        assert_eq!(body.data().await.unwrap()?, "chunk");
+        drop(body);
+        assert_eq!(cancellation.reason(), Some(CancelReason::ResponseBody));
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), stream_closed_rx).await??;
+
+        // The send future is dropped before the response head.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri(format!("http://{}{}", backend_listen_addr, "/slow"))
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        assert!(tokio::time::timeout(
+            tokio::time::Duration::from_millis(100),
+            send(&client, request)
+        )
+        .await
+        .is_err());
+        assert_eq!(cancellation.reason(), Some(CancelReason::Request));
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), slow_dropped_rx).await??;
+
+        //
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
 }
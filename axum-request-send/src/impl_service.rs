@@ -0,0 +1,286 @@
+use core::future::poll_fn;
+use std::sync::{Mutex, PoisonError};
+
+use axum::{
+    body::{Body as AxumBody, Bytes, HttpBody},
+    http::{Request as HttpRequest, Response as HttpResponse, StatusCode},
+    response::Response as AxumResponse,
+};
+use tower_service::Service;
+
+use crate::{
+    cancellation::{guard_body, CancelGuard, CancelReason, Cancellation},
+    error::SendError,
+    forwarded::set_forwarded_headers,
+    framing::normalize_response_framing,
+    hop_by_hop::{remove_hop_by_hop_headers, remove_hop_by_hop_headers_keep_te_trailers},
+    sender::{BoxError, BoxFuture, RequestSender},
+    upgrade::{is_upgrade_request, remove_hop_by_hop_headers_keep_upgrade},
+    Config,
+};
+
+//
+/// Calls `service` directly, without a socket.
+pub async fn send<S, B>(
+    service: &S,
+    http_request: HttpRequest<AxumBody>,
+) -> Result<AxumResponse, SendError>
+where
+    S: Service<HttpRequest<AxumBody>, Response = HttpResponse<B>> + Clone,
+    S::Error: Into<BoxError>,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    send_with_config(service, http_request, &Config::default()).await
+}
+
+pub async fn send_with_config<S, B>(
+    service: &S,
+    http_request: HttpRequest<AxumBody>,
+    config: &Config,
+) -> Result<AxumResponse, SendError>
+where
+    S: Service<HttpRequest<AxumBody>, Response = HttpResponse<B>> + Clone,
+    S::Error: Into<BoxError>,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    oneshot(service.to_owned(), http_request, config).await
+}
+
+async fn oneshot<S, B>(
+    mut service: S,
+    mut http_request: HttpRequest<AxumBody>,
+    config: &Config,
+) -> Result<AxumResponse, SendError>
+where
+    S: Service<HttpRequest<AxumBody>, Response = HttpResponse<B>>,
+    S::Error: Into<BoxError>,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    if let Some(mode) = &config.forwarded {
+        set_forwarded_headers(&mut http_request, mode);
+    }
+    // The `OnUpgrade` extension is passed through, the service upgrades the client connection.
+    let upgrade = is_upgrade_request(&http_request);
+    let downstream_version = http_request.version();
+    let cancellation = http_request.extensions().get::<Cancellation>().cloned();
+    if config.strip_hop_by_hop_headers {
+        if upgrade {
+            remove_hop_by_hop_headers_keep_upgrade(http_request.headers_mut());
+        } else {
+            remove_hop_by_hop_headers_keep_te_trailers(http_request.headers_mut());
+        }
+    }
+
+    let cancel_guard = CancelGuard::new(cancellation.to_owned(), CancelReason::Request);
+    let http_response = call(&mut service, http_request).await;
+    cancel_guard.disarm();
+    let mut http_response = http_response?;
+    let switching = upgrade && http_response.status() == StatusCode::SWITCHING_PROTOCOLS;
+    normalize_response_framing(&mut http_response, downstream_version);
+    if config.strip_hop_by_hop_headers {
+        if switching {
+            remove_hop_by_hop_headers_keep_upgrade(http_response.headers_mut());
+        } else {
+            remove_hop_by_hop_headers(http_response.headers_mut());
+        }
+    }
+
+    // A 101 body is empty, it may be dropped unread.
+    let cancellation = cancellation.filter(|_| !switching);
+    Ok(http_response.map(|body| guard_body(body, cancellation)))
+}
+
+async fn call<S, B>(
+    service: &mut S,
+    http_request: HttpRequest<AxumBody>,
+) -> Result<HttpResponse<B>, SendError>
+where
+    S: Service<HttpRequest<AxumBody>, Response = HttpResponse<B>>,
+    S::Error: Into<BoxError>,
+{
+    poll_fn(|cx| service.poll_ready(cx))
+        .await
+        .map_err(|err| SendError::Connect(err.into()))?;
+    service
+        .call(http_request)
+        .await
+        .map_err(|err| SendError::Other(err.into()))
+}
+
+//
+/// A `RequestSender` that dispatches to a `tower::Service` (e.g. an axum `Router`) in-process.
+///
+/// The service is cloned for every request, so it does not have to be `Sync`.
+pub struct ServiceSender<S>(Mutex<S>);
+
+impl<S> ServiceSender<S> {
+    pub fn new(service: S) -> Self {
+        Self(Mutex::new(service))
+    }
+
+    pub fn service(&self) -> S
+    where
+        S: Clone,
+    {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .to_owned()
+    }
+}
+
+impl<S> Clone for ServiceSender<S>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::new(self.service())
+    }
+}
+
+impl<S> core::fmt::Debug for ServiceSender<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("ServiceSender").finish()
+    }
+}
+
+impl<S, B> RequestSender for ServiceSender<S>
+where
+    S: Service<HttpRequest<AxumBody>, Response = HttpResponse<B>> + Clone + Send,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(oneshot(self.service(), http_request, config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{
+        http::header::CONNECTION,
+        routing::{get, post},
+        Router,
+    };
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        let backend: Router = Router::new()
+            .route("/", get(|| async { ([(CONNECTION, "close")], "backend") }))
+            .route(
+                "/echo",
+                post(|request: HttpRequest<AxumBody>| async move {
+                    axum::body::StreamBody::new(request.into_body())
+                }),
+            );
+        let sender = ServiceSender::new(backend.to_owned());
+
+        //
+        let request = HttpRequest::builder()
+            .uri("http://backend.internal/")
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(CONNECTION).is_none());
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "backend"
+        );
+
+        //
+        let (mut body_tx, body) = AxumBody::channel();
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("http://backend.internal/echo")
+            .body(body)?;
+        let send_task = tokio::task::spawn(async move {
+            body_tx.send_data(Bytes::from_static(b"foo")).await.unwrap();
+            body_tx.send_data(Bytes::from_static(b"bar")).await.unwrap();
+        });
+        let response = send(&backend, request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "foobar");
+        send_task.await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_cancel() -> Result<(), Box<dyn std::error::Error>> {
+        use axum::body::HttpBody as _;
+
+        use crate::cancellation::{CancelReason, Cancellation};
+
+        let backend: Router = Router::new()
+            .route(
+                "/stream",
+                get(|| async {
+                    let (mut body_tx, body) = AxumBody::channel();
+                    tokio::task::spawn(async move {
+                        while body_tx
+                            .send_data(Bytes::from_static(b"chunk"))
+                            .await
+                            .is_ok()
+                        {}
+                    });
+                    axum::body::boxed(body)
+                }),
+            )
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+                    "slow"
+                }),
+            );
+        let sender = ServiceSender::new(backend);
+
+        // Read to the end.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri("/")
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        hyper::body::to_bytes(response.into_body()).await?;
+        assert_eq!(cancellation.reason(), None);
+
+        // The response body is dropped mid-stream.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri("/stream")
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        let mut body = sender.send(request).await?.into_body();
+        assert_eq!(body.data().await.unwrap()?, "chunk");
+        drop(body);
+        assert_eq!(cancellation.reason(), Some(CancelReason::ResponseBody));
+
+        // The send future is dropped before the response head.
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .uri("/slow")
+            .extension(cancellation.to_owned())
+            .body(AxumBody::empty())?;
+        assert!(
+            tokio::time::timeout(tokio::time::Duration::from_millis(50), sender.send(request))
+                .await
+                .is_err()
+        );
+        assert_eq!(cancellation.reason(), Some(CancelReason::Request));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,53 @@
 //
+#[cfg(feature = "impl_hyper")]
+pub mod impl_hyper;
 #[cfg(feature = "impl_isahc")]
 pub mod impl_isahc;
 #[cfg(feature = "impl_reqwest")]
 pub mod impl_reqwest;
+pub mod impl_service;
+
+//
+pub mod auth;
+pub mod body;
+pub mod body_limit;
+pub mod cache;
+pub mod cache_store;
+pub mod cancellation;
+pub mod circuit_breaker;
+pub mod config;
+pub mod error;
+pub mod forwarded;
+pub mod framing;
+pub mod health_check;
+pub mod hop_by_hop;
+pub mod mirror;
+pub mod response_rewrite;
+pub mod retry;
+pub mod reverse_proxy;
+pub mod rewrite;
+pub mod sender;
+pub mod timeout;
+pub mod unix_socket;
+pub mod upgrade;
+pub mod upstream_pool;
+
+pub use auth::{Auth, RequestSigner};
+pub use body_limit::BodyLimit;
+pub use cache::Cache;
+pub use cache_store::{CacheStore, DiskStore, MemoryStore};
+pub use cancellation::Cancellation;
+pub use circuit_breaker::CircuitBreaker;
+pub use config::Config;
+pub use error::SendError;
+pub use health_check::HealthCheck;
+pub use impl_service::ServiceSender;
+pub use mirror::Mirror;
+pub use response_rewrite::ResponseRewrite;
+pub use retry::Retry;
+pub use reverse_proxy::ReverseProxy;
+pub use rewrite::UriRewrite;
+pub use sender::{BoxRequestSender, RequestSender};
+pub use timeout::{RequestTimeout, Timeout};
+pub use unix_socket::UnixSocket;
+pub use upstream_pool::UpstreamPool;
@@ -0,0 +1,480 @@
+use core::time::Duration;
+use std::sync::Arc;
+
+use axum::{
+    body::{Body as AxumBody, Bytes, HttpBody as _},
+    http::{
+        header::HOST, request::Parts as HttpRequestParts, HeaderMap, HeaderValue, Method,
+        Request as HttpRequest, StatusCode, Uri,
+    },
+    response::Response as AxumResponse,
+};
+use futures_util::stream;
+use rand::Rng as _;
+use tokio::sync::oneshot;
+
+use crate::{
+    body::tee_with_trailers,
+    cancellation::Cancellation,
+    error::SendError,
+    retry::replay_extensions,
+    rewrite::UriRewrite,
+    sender::{BoxFuture, RequestSender},
+    unix_socket::UnixSocket,
+    Config,
+};
+
+//
+/// Passed to the `on_response` callback of `Mirror`.
+#[derive(Debug)]
+pub struct MirrorComparison {
+    pub method: Method,
+    /// The shadow request URI.
+    pub uri: Uri,
+    /// `None` when the primary call failed.
+    pub primary: Option<(StatusCode, HeaderMap)>,
+    pub shadow: Result<AxumResponse, SendError>,
+}
+
+type OnResponse = Arc<dyn Fn(MirrorComparison) + Send + Sync>;
+
+//
+/// Sends a sampled copy of each request to a shadow upstream, on a detached task.
+///
+/// The primary call is unchanged. A request body of known length up to `max_body_size`
+/// is read before the primary call, a streamed one is teed while the primary call reads it,
+/// the copy is dropped when it is larger than `max_body_size` or is not read to the end.
+/// The shadow response is dropped, or passed to `on_response`.
+#[derive(Clone)]
+pub struct Mirror<S, M> {
+    inner: S,
+    shadow: Arc<M>,
+    rewrite: Arc<UriRewrite>,
+    sample_rate: f64,
+    max_body_size: usize,
+    timeout: Duration,
+    on_response: Option<OnResponse>,
+}
+
+impl<S, M> core::fmt::Debug for Mirror<S, M>
+where
+    S: core::fmt::Debug,
+    M: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Mirror")
+            .field("inner", &self.inner)
+            .field("shadow", &self.shadow)
+            .field("rewrite", &self.rewrite)
+            .field("sample_rate", &self.sample_rate)
+            .field("max_body_size", &self.max_body_size)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, M> Mirror<S, M> {
+    /// `shadow` is the client for the shadow upstream, see `UriRewrite::upstream`.
+    pub fn new(inner: S, shadow: M, upstream: Uri) -> Self {
+        Self {
+            inner,
+            shadow: Arc::new(shadow),
+            rewrite: Arc::new(UriRewrite::new().upstream(upstream)),
+            sample_rate: 1.0,
+            max_body_size: 64 * 1024,
+            timeout: Duration::from_secs(10),
+            on_response: None,
+        }
+    }
+
+    /// Replaces the shadow URI rewrite, including the upstream given to `new`.
+    pub fn rewrite(mut self, rewrite: UriRewrite) -> Self {
+        self.rewrite = Arc::new(rewrite);
+        self
+    }
+
+    /// Between `0.0` and `1.0`.
+    pub fn sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn on_response(mut self, f: impl Fn(MirrorComparison) + Send + Sync + 'static) -> Self {
+        self.on_response = Some(Arc::new(f));
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    async fn shadow_parts(&self, parts: &mut HttpRequestParts) -> Option<HttpRequestParts> {
+        let uri = parts.uri.to_owned();
+        let rewritten = self.rewrite.apply_to_parts(parts).await;
+        let shadow_uri = core::mem::replace(&mut parts.uri, uri);
+        rewritten.ok()?;
+
+        let mut shadow = HttpRequest::new(()).into_parts().0;
+        shadow.method = parts.method.to_owned();
+        shadow.version = parts.version;
+        shadow.headers = parts.headers.to_owned();
+        shadow.extensions = replay_extensions(&parts.extensions);
+        // The shadow upstream is not behind the primary's socket,
+        // and a dropped or timed out shadow call does not cancel the primary.
+        shadow.extensions.remove::<UnixSocket>();
+        shadow.extensions.remove::<Cancellation>();
+        if let Some(authority) = shadow_uri.authority() {
+            if let Ok(value) = HeaderValue::from_str(authority.as_str()) {
+                shadow.headers.insert(HOST, value);
+            }
+        }
+        shadow.uri = shadow_uri;
+        Some(shadow)
+    }
+}
+
+impl<S, M> RequestSender for Mirror<S, M>
+where
+    S: RequestSender,
+    M: RequestSender + 'static,
+    M::Error: Into<SendError>,
+{
+    type Error = S::Error;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            if !rand::thread_rng().gen_bool(self.sample_rate) {
+                return self.inner.send_with_config(http_request, config).await;
+            }
+
+            let (mut parts, body) = http_request.into_parts();
+            let shadow_parts = match self.shadow_parts(&mut parts).await {
+                Some(x) => x,
+                None => {
+                    return self
+                        .inner
+                        .send_with_config(HttpRequest::from_parts(parts, body), config)
+                        .await
+                }
+            };
+
+            let (body_tx, body_rx) = oneshot::channel();
+            let body = match body.size_hint().exact() {
+                // Nothing to tee, e.g. a `GET`.
+                Some(0) => {
+                    body_tx.send(Bytes::new()).ok();
+                    body
+                }
+                // Read up front, the primary body keeps its size hint.
+                Some(length) if length <= self.max_body_size as u64 => buffer(body, body_tx).await,
+                // Too large to be mirrored.
+                Some(_) => body,
+                None => tee_with_trailers(body, self.max_body_size, body_tx),
+            };
+
+            let (primary_tx, primary_rx) = oneshot::channel();
+            tokio::task::spawn(send_shadow(
+                self.shadow.to_owned(),
+                shadow_parts,
+                config.to_owned(),
+                self.timeout,
+                body_rx,
+                primary_rx,
+                self.on_response.to_owned(),
+            ));
+
+            let result = self
+                .inner
+                .send_with_config(HttpRequest::from_parts(parts, body), config)
+                .await;
+            primary_tx
+                .send(
+                    result
+                        .as_ref()
+                        .ok()
+                        .map(|x| (x.status(), x.headers().to_owned())),
+                )
+                .ok();
+            result
+        })
+    }
+}
+
+//
+/// Reads a sized `body` and sends a copy to `tx`, the same body is returned.
+async fn buffer(mut body: AxumBody, tx: oneshot::Sender<Bytes>) -> AxumBody {
+    let mut chunks = vec![];
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => chunks.push(chunk),
+            Err(err) => {
+                // The primary call fails as it would have, after what was read.
+                let chunks = chunks.into_iter().map(Ok).chain([Err(err)]);
+                return AxumBody::wrap_stream(stream::iter(chunks));
+            }
+        }
+    }
+    let bytes = Bytes::from(chunks.concat());
+    tx.send(bytes.to_owned()).ok();
+    match body.trailers().await {
+        Ok(Some(trailers)) => {
+            let (mut body_tx, body) = AxumBody::channel();
+            body_tx.try_send_data(bytes).ok();
+            body_tx.send_trailers(trailers).await.ok();
+            body
+        }
+        Ok(None) => AxumBody::from(bytes),
+        Err(err) => AxumBody::wrap_stream(stream::iter([Ok(bytes), Err(err)])),
+    }
+}
+
+async fn send_shadow<M>(
+    shadow: Arc<M>,
+    parts: HttpRequestParts,
+    config: Config,
+    timeout: Duration,
+    body_rx: oneshot::Receiver<Bytes>,
+    primary_rx: oneshot::Receiver<Option<(StatusCode, HeaderMap)>>,
+    on_response: Option<OnResponse>,
+) where
+    M: RequestSender,
+    M::Error: Into<SendError>,
+{
+    let method = parts.method.to_owned();
+    let uri = parts.uri.to_owned();
+
+    let result = tokio::time::timeout(timeout, async {
+        // Dropped sender, the body was too large or not read to the end.
+        let body = body_rx.await.ok()?;
+        let http_request = HttpRequest::from_parts(parts, AxumBody::from(body));
+        Some(
+            shadow
+                .send_with_config(http_request, &config)
+                .await
+                .map_err(Into::into),
+        )
+    })
+    .await;
+    let shadow = match result {
+        Ok(Some(x)) => x,
+        Ok(None) => return,
+        Err(err) => Err(SendError::Timeout(err.into())),
+    };
+
+    if let Some(on_response) = on_response {
+        let primary = primary_rx.await.ok().flatten();
+        on_response(MirrorComparison {
+            method,
+            uri,
+            primary,
+            shadow,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use axum::{
+        http::header::CONTENT_LENGTH,
+        routing::{any, post},
+        Router,
+    };
+
+    use crate::impl_service::ServiceSender;
+
+    fn echo(name: &'static str) -> ServiceSender<Router> {
+        ServiceSender::new(
+            Router::new()
+                .route(
+                    "/framing",
+                    any(|request: HttpRequest<AxumBody>| async move {
+                        let size = request.body().size_hint().exact();
+                        let content_length = request.headers().contains_key(CONTENT_LENGTH);
+                        let mut body = request.into_body();
+                        while let Some(chunk) = body.data().await {
+                            chunk.unwrap();
+                        }
+                        let trailers = body.trailers().await.unwrap();
+                        format!(
+                            "{size:?} {content_length} {:?}",
+                            trailers.and_then(|x| x.get("grpc-status").cloned())
+                        )
+                    }),
+                )
+                .route(
+                    "/echo",
+                    post(move |request: HttpRequest<AxumBody>| async move {
+                        let host = request.headers().get(HOST).unwrap().to_owned();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        format!(
+                            "{name} {} {}",
+                            host.to_str().unwrap(),
+                            String::from_utf8_lossy(&body)
+                        )
+                    }),
+                ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let tx = Mutex::new(tx);
+        let sender = Mirror::new(
+            echo("primary"),
+            echo("shadow"),
+            "http://shadow.internal".parse()?,
+        )
+        .max_body_size(6)
+        .on_response(move |comparison| {
+            tx.lock().unwrap().send(comparison).unwrap();
+        });
+
+        //
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("http://primary.internal/echo")
+            .header(HOST, "primary.internal")
+            .body(AxumBody::from("foobar"))?;
+        let response = sender.send(request).await?;
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "primary primary.internal foobar"
+        );
+
+        let comparison = rx.recv().await.unwrap();
+        assert_eq!(comparison.uri, "http://shadow.internal/echo");
+        assert_eq!(comparison.primary.unwrap().0, StatusCode::OK);
+        let shadow = comparison.shadow.unwrap();
+        assert_eq!(
+            hyper::body::to_bytes(shadow.into_body()).await?,
+            "shadow shadow.internal foobar"
+        );
+
+        // Over `max_body_size`, not mirrored.
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("http://primary.internal/echo")
+            .header(HOST, "primary.internal")
+            .body(AxumBody::from("foobarbaz"))?;
+        let response = sender.send(request).await?;
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "primary primary.internal foobarbaz"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+
+        // The primary body keeps its size hint and trailers, `Content-Length` is not added.
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("http://primary.internal/framing")
+            .body(AxumBody::from("foobar"))?;
+        let response = sender.send(request).await?;
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "Some(6) false None"
+        );
+        rx.recv().await.unwrap();
+
+        let (mut body_tx, body) = AxumBody::channel();
+        body_tx.send_data(Bytes::from_static(b"foo")).await?;
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        body_tx.send_trailers(trailers).await?;
+        drop(body_tx);
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("http://primary.internal/framing")
+            .body(body)?;
+        let response = sender.send(request).await?;
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "None false Some(\"0\")"
+        );
+        rx.recv().await.unwrap();
+
+        let request = HttpRequest::builder()
+            .uri("http://primary.internal/framing")
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await?,
+            "Some(0) false None"
+        );
+        rx.recv().await.unwrap();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_cancellation() -> Result<(), Box<dyn std::error::Error>> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let shadow = ServiceSender::new(Router::new().route(
+            "/echo",
+            post(move || {
+                let tx = tx.to_owned();
+                async move {
+                    tx.send(()).unwrap();
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    "slow"
+                }
+            }),
+        ));
+
+        // The shadow response is dropped unread.
+        let sender = Mirror::new(
+            echo("primary"),
+            shadow.to_owned(),
+            "http://shadow.internal".parse()?,
+        );
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("http://primary.internal/echo")
+            .header(HOST, "primary.internal")
+            .extension(cancellation.to_owned())
+            .body(AxumBody::from("foo"))?;
+        let response = sender.send(request).await?;
+        hyper::body::to_bytes(response.into_body()).await?;
+        rx.recv().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(cancellation.reason(), None);
+
+        // The shadow call times out.
+        let sender = Mirror::new(echo("primary"), shadow, "http://shadow.internal".parse()?)
+            .timeout(Duration::from_millis(10));
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .method("POST")
+            .uri("http://primary.internal/echo")
+            .header(HOST, "primary.internal")
+            .extension(cancellation.to_owned())
+            .body(AxumBody::from("foo"))?;
+        let response = sender.send(request).await?;
+        hyper::body::to_bytes(response.into_body()).await?;
+        rx.recv().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(cancellation.reason(), None);
+
+        Ok(())
+    }
+}
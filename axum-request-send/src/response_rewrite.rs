@@ -0,0 +1,259 @@
+use axum::http::{
+    header::{CONTENT_LOCATION, LOCATION, REFRESH, SET_COOKIE},
+    HeaderMap, HeaderName, HeaderValue, Response as HttpResponse, Uri,
+};
+
+//
+/// Rewrites the upstream origin and path prefix in response headers back to the public ones,
+/// like nginx's `proxy_redirect`, `proxy_cookie_domain` and `proxy_cookie_path`.
+///
+/// `Location`, `Content-Location` and `Refresh` are rewritten with the `redirect` rules,
+/// the `Domain` and `Path` attributes of `Set-Cookie` with the `cookie_domain` / `cookie_path` rules.
+/// The first matching rule wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseRewrite {
+    redirects: Vec<(String, String)>,
+    cookie_domains: Vec<(String, String)>,
+    cookie_paths: Vec<(String, String)>,
+}
+
+impl ResponseRewrite {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rules from `upstream` (e.g. `http://backend:8080/v1`) to `public` (e.g. `https://example.com/api`),
+    /// for absolute and path-only locations, cookie domains and cookie paths.
+    pub fn reverse(upstream: &Uri, public: &Uri) -> Self {
+        let origin = |uri: &Uri| match (uri.scheme_str(), uri.authority()) {
+            (Some(scheme), Some(authority)) => Some(format!("{scheme}://{authority}")),
+            _ => None,
+        };
+        let upstream_path = upstream.path().trim_end_matches('/');
+        let public_path = public.path().trim_end_matches('/');
+
+        let mut rewrite = Self::new();
+        if let (Some(upstream_origin), Some(public_origin)) = (origin(upstream), origin(public)) {
+            rewrite = rewrite.redirect(
+                format!("{upstream_origin}{upstream_path}/"),
+                format!("{public_origin}{public_path}/"),
+            );
+        }
+        if upstream_path != public_path {
+            rewrite = rewrite
+                .redirect(format!("{upstream_path}/"), format!("{public_path}/"))
+                .cookie_path(format!("{upstream_path}/"), format!("{public_path}/"));
+        }
+        if let (Some(upstream_host), Some(public_host)) = (upstream.host(), public.host()) {
+            rewrite = rewrite.cookie_domain(upstream_host, public_host);
+        }
+        rewrite
+    }
+
+    /// Replaces the `from` prefix of a location, absolute (`http://backend/v1/`) or path-only (`/v1/`).
+    pub fn redirect(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.redirects.push((from.into(), to.into()));
+        self
+    }
+
+    /// Compared case-insensitively, ignoring a leading `.`.
+    pub fn cookie_domain(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.cookie_domains.push((from.into(), to.into()));
+        self
+    }
+
+    /// Replaces the `from` prefix of the cookie path.
+    pub fn cookie_path(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.cookie_paths.push((from.into(), to.into()));
+        self
+    }
+
+    //
+    pub fn apply<B>(&self, http_response: &mut HttpResponse<B>) {
+        self.apply_to_headers(http_response.headers_mut())
+    }
+
+    pub fn apply_to_headers(&self, headers: &mut HeaderMap) {
+        if !self.redirects.is_empty() {
+            for name in [LOCATION, CONTENT_LOCATION] {
+                self.rewrite_header(headers, name, |x| self.rewrite_location(x));
+            }
+            self.rewrite_header(headers, REFRESH, |x| self.rewrite_refresh(x));
+        }
+        if !self.cookie_domains.is_empty() || !self.cookie_paths.is_empty() {
+            self.rewrite_header(headers, SET_COOKIE, |x| Some(self.rewrite_cookie(x)));
+        }
+    }
+
+    fn rewrite_header(
+        &self,
+        headers: &mut HeaderMap,
+        name: HeaderName,
+        f: impl Fn(&str) -> Option<String>,
+    ) {
+        let values = headers
+            .get_all(&name)
+            .iter()
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(&f)
+                    .and_then(|x| HeaderValue::from_str(&x).ok())
+                    .unwrap_or_else(|| value.to_owned())
+            })
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return;
+        }
+        headers.remove(&name);
+        for value in values {
+            headers.append(name.to_owned(), value);
+        }
+    }
+
+    fn rewrite_location(&self, location: &str) -> Option<String> {
+        self.redirects
+            .iter()
+            .find_map(|(from, to)| replace_prefix(location, from, to))
+    }
+
+    /// `5; url=http://backend/v1/next`
+    fn rewrite_refresh(&self, refresh: &str) -> Option<String> {
+        let (delay, rest) = refresh.split_once(';')?;
+        let rest = rest.trim_start();
+        let (key, url) = rest.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case("url") {
+            return None;
+        }
+        let url = url.trim().trim_matches(|c| c == '\'' || c == '"');
+        let url = self.rewrite_location(url)?;
+        Some(format!("{delay}; {}={url}", key.trim()))
+    }
+
+    fn rewrite_cookie(&self, cookie: &str) -> String {
+        cookie
+            .split(';')
+            .enumerate()
+            .map(|(i, attribute)| {
+                if i == 0 {
+                    return attribute.to_owned();
+                }
+                let (key, value) = match attribute.split_once('=') {
+                    Some(x) => x,
+                    None => return attribute.to_owned(),
+                };
+                let key_trimmed = key.trim();
+                let value = value.trim();
+                let rewritten = if key_trimmed.eq_ignore_ascii_case("domain") {
+                    let domain = value.trim_start_matches('.');
+                    self.cookie_domains
+                        .iter()
+                        .find(|(from, _)| from.trim_start_matches('.').eq_ignore_ascii_case(domain))
+                        .map(|(_, to)| to.to_owned())
+                } else if key_trimmed.eq_ignore_ascii_case("path") {
+                    self.cookie_paths
+                        .iter()
+                        .find_map(|(from, to)| replace_prefix(value, from, to))
+                } else {
+                    None
+                };
+                match rewritten {
+                    Some(value) => format!("{key}={value}"),
+                    None => attribute.to_owned(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
+/// `/v1` also matches the `/v1/` prefix.
+fn replace_prefix(value: &str, from: &str, to: &str) -> Option<String> {
+    let rest = value
+        .strip_prefix(from)
+        .or_else(|| (from.ends_with('/') && value == &from[..from.len() - 1]).then_some(""))?;
+    Some(format!("{to}{rest}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverse() {
+        let rewrite = ResponseRewrite::reverse(
+            &"http://backend.internal:8080/v1".parse().unwrap(),
+            &"https://example.com/api".parse().unwrap(),
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LOCATION,
+            "http://backend.internal:8080/v1/login?next=/v1/home"
+                .parse()
+                .unwrap(),
+        );
+        headers.insert(CONTENT_LOCATION, "/v1/users/1".parse().unwrap());
+        headers.insert(
+            REFRESH,
+            "5; url=http://backend.internal:8080/v1".parse().unwrap(),
+        );
+        headers.append(
+            SET_COOKIE,
+            "sid=1; Domain=.backend.internal; Path=/v1/; HttpOnly"
+                .parse()
+                .unwrap(),
+        );
+        headers.append(
+            SET_COOKIE,
+            "theme=dark; Domain=other.com; Path=/".parse().unwrap(),
+        );
+        rewrite.apply_to_headers(&mut headers);
+
+        assert_eq!(
+            headers.get(LOCATION).unwrap(),
+            "https://example.com/api/login?next=/v1/home"
+        );
+        assert_eq!(headers.get(CONTENT_LOCATION).unwrap(), "/api/users/1");
+        assert_eq!(
+            headers.get(REFRESH).unwrap(),
+            "5; url=https://example.com/api/"
+        );
+        let cookies = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|x| x.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cookies,
+            [
+                "sid=1; Domain=example.com; Path=/api/; HttpOnly",
+                "theme=dark; Domain=other.com; Path=/"
+            ]
+        );
+
+        // Upstream at the root, one rule for `/`.
+        let rewrite = ResponseRewrite::reverse(
+            &"http://backend.internal:8080".parse().unwrap(),
+            &"https://example.com/api".parse().unwrap(),
+        );
+        assert_eq!(rewrite.cookie_paths, [("/".to_owned(), "/api/".to_owned())]);
+        let mut headers = HeaderMap::new();
+        headers.insert(SET_COOKIE, "sid=1; Path=/".parse().unwrap());
+        rewrite.apply_to_headers(&mut headers);
+        assert_eq!(headers.get(SET_COOKIE).unwrap(), "sid=1; Path=/api/");
+    }
+
+    #[test]
+    fn test_unmatched() {
+        let rewrite = ResponseRewrite::new().redirect("http://backend/", "https://example.com/");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, "https://elsewhere.com/".parse().unwrap());
+        headers.insert(REFRESH, "5".parse().unwrap());
+        rewrite.apply_to_headers(&mut headers);
+        assert_eq!(headers.get(LOCATION).unwrap(), "https://elsewhere.com/");
+        assert_eq!(headers.get(REFRESH).unwrap(), "5");
+    }
+}
@@ -0,0 +1,489 @@
+use core::time::Duration;
+use std::time::SystemTime;
+
+use axum::{
+    body::{Body as AxumBody, Bytes, HttpBody as _},
+    extract::{ConnectInfo, MatchedPath, OriginalUri},
+    http::{
+        header::{CONTENT_LENGTH, RETRY_AFTER},
+        request::Parts as HttpRequestParts,
+        Extensions as HttpExtensions, HeaderMap, Method, Request as HttpRequest, StatusCode,
+    },
+    response::Response as AxumResponse,
+};
+use futures_util::{stream, StreamExt as _};
+use rand::Rng as _;
+
+use crate::{
+    cancellation::Cancellation,
+    error::SendError,
+    sender::{BoxFuture, RequestSender},
+    timeout::RequestTimeout,
+    unix_socket::UnixSocket,
+    upgrade::is_upgrade_request,
+    Config,
+};
+
+//
+/// Retries idempotent requests on connection errors and on `retry_on_status`.
+///
+/// The request body is buffered up to `max_body_size` so that it can be replayed,
+/// larger bodies are sent once, without retries.
+#[derive(Debug, Clone)]
+pub struct Retry<S> {
+    inner: S,
+    max_retries: usize,
+    max_body_size: usize,
+    backoff: Backoff,
+    retry_on_status: Vec<StatusCode>,
+    respect_retry_after: bool,
+}
+
+//
+/// Exponential backoff with full jitter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(50),
+            max: Duration::from_secs(2),
+            jitter: true,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn delay(&self, retry: usize) -> Duration {
+        let delay = self
+            .base
+            .saturating_mul(1_u32.checked_shl(retry as u32).unwrap_or(u32::MAX))
+            .min(self.max);
+        if self.jitter && !delay.is_zero() {
+            rand::thread_rng().gen_range(Duration::ZERO..=delay)
+        } else {
+            delay
+        }
+    }
+}
+
+impl<S> Retry<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            max_retries: 2,
+            max_body_size: 64 * 1024,
+            backoff: Backoff::default(),
+            retry_on_status: vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            respect_retry_after: true,
+        }
+    }
+
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn retry_on_status(mut self, retry_on_status: Vec<StatusCode>) -> Self {
+        self.retry_on_status = retry_on_status;
+        self
+    }
+
+    /// Wait for `Retry-After` (when it is not longer than `Backoff::max`) instead of the backoff.
+    pub fn respect_retry_after(mut self, respect_retry_after: bool) -> Self {
+        self.respect_retry_after = respect_retry_after;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn retry_delay(&self, retry: usize, response: Option<&AxumResponse>) -> Option<Duration> {
+        let backoff = self.backoff.delay(retry);
+        match response {
+            Some(response) if self.respect_retry_after => match retry_after(response.headers()) {
+                Some(x) if x > self.backoff.max => None,
+                Some(x) => Some(x),
+                None => Some(backoff),
+            },
+            _ => Some(backoff),
+        }
+    }
+}
+
+impl<S> RequestSender for Retry<S>
+where
+    S: RequestSender,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            // The upgraded connection can not be replayed.
+            if self.max_retries == 0
+                || !is_idempotent(http_request.method())
+                || is_upgrade_request(&http_request)
+            {
+                return self
+                    .inner
+                    .send_with_config(http_request, config)
+                    .await
+                    .map_err(Into::into);
+            }
+
+            let (mut parts, body) = http_request.into_parts();
+            let body = match buffer_body(&parts.headers, body, self.max_body_size).await? {
+                Ok(body) => body,
+                Err(body) => {
+                    let http_request = HttpRequest::from_parts(parts, body);
+                    return self
+                        .inner
+                        .send_with_config(http_request, config)
+                        .await
+                        .map_err(Into::into);
+                }
+            };
+
+            // The first attempt gets all the extensions, retries only the replayable ones.
+            let replay = replay_extensions(&parts.extensions);
+            let cancellation = parts.extensions.get::<Cancellation>().cloned();
+            let mut extensions = Some(core::mem::take(&mut parts.extensions));
+            let mut retry = 0;
+            loop {
+                let mut extensions = extensions
+                    .take()
+                    .unwrap_or_else(|| replay_extensions(&replay));
+                // A response dropped to retry is not a cancellation of the request.
+                let attempt = cancellation.as_ref().map(Cancellation::attempt);
+                if let Some(attempt) = &attempt {
+                    extensions.insert(attempt.to_owned());
+                }
+                let http_request = replay_request(&parts, extensions, body.to_owned());
+                let result = self
+                    .inner
+                    .send_with_config(http_request, config)
+                    .await
+                    .map_err(Into::into);
+
+                let delay = match &result {
+                    _ if retry >= self.max_retries => None,
+                    Ok(response) if self.retry_on_status.contains(&response.status()) => {
+                        self.retry_delay(retry, Some(response))
+                    }
+                    Ok(_) => None,
+                    Err(err) if is_retryable(err) => self.retry_delay(retry, None),
+                    Err(_) => None,
+                };
+                match delay {
+                    Some(delay) => {
+                        if let Some(attempt) = &attempt {
+                            attempt.detach();
+                        }
+                        drop(result);
+                        tokio::time::sleep(delay).await;
+                        retry += 1;
+                    }
+                    None => return result,
+                }
+            }
+        })
+    }
+}
+
+//
+pub fn is_idempotent(method: &Method) -> bool {
+    // Ref https://www.rfc-editor.org/rfc/rfc9110#section-9.2.2
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+fn is_retryable(err: &SendError) -> bool {
+    matches!(
+        err,
+        SendError::Connect(_) | SendError::Protocol(_) | SendError::BodyStream(_)
+    )
+}
+
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    httpdate::parse_http_date(value)
+        .ok()?
+        .duration_since(SystemTime::now())
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+/// `Ok` with the whole body when it fits in `max_body_size`,
+/// otherwise `Err` with an equivalent body for a single attempt.
+pub(crate) async fn buffer_body(
+    headers: &HeaderMap,
+    mut body: AxumBody,
+    max_body_size: usize,
+) -> Result<Result<Bytes, AxumBody>, SendError> {
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    if content_length
+        .map(|x| x > max_body_size as u64)
+        .unwrap_or(false)
+    {
+        return Ok(Err(body));
+    }
+
+    let mut chunks = vec![];
+    let mut size = 0;
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| SendError::BodyStream(err.into()))?;
+        size += chunk.len();
+        chunks.push(chunk);
+
+        if size > max_body_size {
+            let prefix = stream::iter(chunks.into_iter().map(Ok::<_, axum::Error>));
+            let rest = body.map(|x| x.map_err(axum::Error::new));
+            return Ok(Err(AxumBody::wrap_stream(prefix.chain(rest))));
+        }
+    }
+
+    Ok(Ok(chunks.concat().into()))
+}
+
+fn replay_request(
+    parts: &HttpRequestParts,
+    extensions: HttpExtensions,
+    body: Bytes,
+) -> HttpRequest<AxumBody> {
+    let mut http_request = HttpRequest::new(AxumBody::from(body));
+    *http_request.method_mut() = parts.method.to_owned();
+    *http_request.uri_mut() = parts.uri.to_owned();
+    *http_request.version_mut() = parts.version;
+    *http_request.headers_mut() = parts.headers.to_owned();
+    *http_request.extensions_mut() = extensions;
+    http_request
+}
+
+/// `Extensions` is not `Clone`, only the extensions this crate reads are kept.
+/// Path params and `OnUpgrade` can not be copied.
+pub(crate) fn replay_extensions(extensions: &HttpExtensions) -> HttpExtensions {
+    let mut replay = HttpExtensions::new();
+    if let Some(x) = extensions.get::<ConnectInfo<std::net::SocketAddr>>() {
+        replay.insert(x.to_owned());
+    }
+    if let Some(x) = extensions.get::<OriginalUri>() {
+        replay.insert(x.to_owned());
+    }
+    if let Some(x) = extensions.get::<MatchedPath>() {
+        replay.insert(x.to_owned());
+    }
+    if let Some(x) = extensions.get::<RequestTimeout>() {
+        replay.insert(x.to_owned());
+    }
+    if let Some(x) = extensions.get::<UnixSocket>() {
+        replay.insert(x.to_owned());
+    }
+    if let Some(x) = extensions.get::<Cancellation>() {
+        replay.insert(x.to_owned());
+    }
+    replay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::{
+        routing::{get, put},
+        Router,
+    };
+
+    use crate::impl_service::ServiceSender;
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff {
+            base: Duration::from_millis(100),
+            max: Duration::from_millis(300),
+            jitter: false,
+        };
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(1), Duration::from_millis(200));
+        assert_eq!(backoff.delay(2), Duration::from_millis(300));
+        assert_eq!(backoff.delay(100), Duration::from_millis(300));
+
+        let backoff = Backoff {
+            jitter: true,
+            ..backoff
+        };
+        assert!(backoff.delay(5) <= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let backend: Router = Router::new().route(
+            "/",
+            put({
+                let attempts = attempts.clone();
+                move |body: String| async move {
+                    if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                        (StatusCode::SERVICE_UNAVAILABLE, [(RETRY_AFTER, "0")], body)
+                    } else {
+                        (StatusCode::OK, [(RETRY_AFTER, "")], body)
+                    }
+                }
+            })
+            .post(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+        );
+        let sender = Retry::new(ServiceSender::new(backend)).backoff(Backoff {
+            base: Duration::from_millis(1),
+            ..Default::default()
+        });
+
+        //
+        let request = HttpRequest::builder()
+            .method(Method::PUT)
+            .uri("/")
+            .body(AxumBody::from("foo"))?;
+        let response = sender.send(request).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "foo");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        //
+        attempts.store(0, Ordering::SeqCst);
+        let request = HttpRequest::builder()
+            .method(Method::PUT)
+            .uri("/")
+            .body(AxumBody::from("foo"))?;
+        let response = sender.to_owned().max_body_size(2).send(request).await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "foo");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        //
+        let request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri("/")
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Responses dropped to retry are not recorded, the last one is.
+        attempts.store(0, Ordering::SeqCst);
+        let cancellation = Cancellation::new();
+        let request = HttpRequest::builder()
+            .method(Method::PUT)
+            .uri("/")
+            .extension(cancellation.to_owned())
+            .body(AxumBody::from("foo"))?;
+        let response = sender.send(request).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(cancellation.reason(), None);
+        drop(response);
+        assert!(cancellation.is_cancelled());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_extensions() -> Result<(), Box<dyn std::error::Error>> {
+        use axum::{
+            extract::{FromRequestParts as _, RawPathParams},
+            http::header::{CONNECTION, UPGRADE},
+        };
+
+        #[derive(Debug, Clone)]
+        struct Marker;
+
+        let backend: Router = Router::new().route(
+            "/users/7",
+            get(|request: HttpRequest<AxumBody>| async move {
+                let (mut parts, _) = request.into_parts();
+                let params = RawPathParams::from_request_parts(&mut parts, &())
+                    .await
+                    .unwrap();
+                let (name, value) = params.iter().next().unwrap();
+                format!("{name}={value}")
+            }),
+        );
+        let sender = Retry::new(ServiceSender::new(backend)).backoff(Backoff {
+            base: Duration::from_millis(1),
+            ..Default::default()
+        });
+
+        // Path params of the downstream route.
+        let proxy = ServiceSender::new(Router::new().route(
+            "/users/:id",
+            get(move |request: HttpRequest<AxumBody>| async move {
+                sender.send(request).await.unwrap()
+            }),
+        ));
+        let request = HttpRequest::builder()
+            .uri("/users/7")
+            .body(AxumBody::empty())?;
+        let response = proxy.send(request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "id=7");
+
+        // Upgrade requests are sent once, with their extensions.
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let sender = Retry::new(ServiceSender::new(Router::new().route(
+            "/ws",
+            get({
+                let attempts = attempts.clone();
+                move |request: HttpRequest<AxumBody>| async move {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    assert!(request.extensions().get::<Marker>().is_some());
+                    StatusCode::SERVICE_UNAVAILABLE
+                }
+            }),
+        )));
+        let request = HttpRequest::builder()
+            .uri("/ws")
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "websocket")
+            .extension(Marker)
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use core::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::sync::Arc;
+
+use axum::{
+    body::Body as AxumBody,
+    http::{header::HOST, uri::Authority, HeaderValue, Request as HttpRequest, Uri},
+    response::{IntoResponse as _, Response as AxumResponse},
+};
+use tower_service::Service;
+
+use crate::{
+    cancellation::{CancelReason, Cancellation},
+    error::SendError,
+    forwarded::set_forwarded_headers,
+    response_rewrite::ResponseRewrite,
+    rewrite::UriRewrite,
+    sender::RequestSender,
+    unix_socket::UnixSocket,
+    Config,
+};
+
+//
+/// A `tower::Service` that forwards every request to `upstream`,
+/// keeping the path and query. Mount it with `Router::nest_service`.
+///
+/// Send errors are rendered as 502 / 504 responses, see `SendError`.
+/// A `Cancellation` is inserted into every request that has none, see `on_cancel`.
+#[derive(Debug, Clone)]
+pub struct ReverseProxy<C> {
+    client: C,
+    rewrite: Arc<UriRewrite>,
+    response_rewrite: Option<Arc<ResponseRewrite>>,
+    preserve_host: bool,
+    unix_socket: Option<UnixSocket>,
+    cancellation: Cancellation,
+    config: Arc<Config>,
+}
+
+impl<C> ReverseProxy<C> {
+    /// The path of `upstream` (if any) is used as `add_prefix`.
+    pub fn new(client: C, upstream: Uri) -> Self {
+        let mut rewrite = UriRewrite::new().upstream(upstream);
+        if rewrite.target_authority().is_none() {
+            rewrite = rewrite.authority(Authority::from_static("localhost"));
+        }
+
+        Self {
+            client,
+            rewrite: Arc::new(rewrite),
+            response_rewrite: None,
+            preserve_host: false,
+            unix_socket: None,
+            cancellation: Cancellation::new(),
+            config: Arc::new(Config::default()),
+        }
+    }
+
+    pub fn strip_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.rewrite = Arc::new(self.rewrite.as_ref().to_owned().strip_prefix(prefix));
+        self
+    }
+
+    pub fn add_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.rewrite = Arc::new(self.rewrite.as_ref().to_owned().add_prefix(prefix));
+        self
+    }
+
+    /// Replaces the whole rewrite, including the upstream given to `new`.
+    pub fn rewrite(mut self, rewrite: UriRewrite) -> Self {
+        self.rewrite = Arc::new(rewrite);
+        self
+    }
+
+    /// Applied to the upstream response headers, e.g. `ResponseRewrite::reverse`.
+    pub fn response_rewrite(mut self, response_rewrite: ResponseRewrite) -> Self {
+        self.response_rewrite = Some(Arc::new(response_rewrite));
+        self
+    }
+
+    /// Keep the client's `Host` instead of using the upstream authority.
+    pub fn preserve_host(mut self, preserve_host: bool) -> Self {
+        self.preserve_host = preserve_host;
+        self
+    }
+
+    /// Sends through `unix_socket`, the upstream given to `new` is then only used for the URI and `Host`.
+    pub fn unix_socket(mut self, unix_socket: UnixSocket) -> Self {
+        self.unix_socket = Some(unix_socket);
+        self
+    }
+
+    /// Called when the client goes away before the upstream call completes.
+    pub fn on_cancel(mut self, f: impl Fn(CancelReason) + Send + Sync + 'static) -> Self {
+        self.cancellation = Cancellation::new().on_cancel(f);
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    //
+    async fn prepare(
+        &self,
+        mut http_request: HttpRequest<AxumBody>,
+    ) -> Result<HttpRequest<AxumBody>, SendError> {
+        // From the incoming request, before the URI points to the upstream.
+        if let Some(mode) = &self.config.forwarded {
+            set_forwarded_headers(&mut http_request, mode);
+        }
+        let mut http_request = self.rewrite.apply(http_request).await?;
+
+        if !self.preserve_host {
+            if let Some(authority) = http_request.uri().authority() {
+                if let Ok(value) = HeaderValue::from_str(authority.as_str()) {
+                    http_request.headers_mut().insert(HOST, value);
+                }
+            }
+        }
+        if let Some(unix_socket) = &self.unix_socket {
+            http_request.extensions_mut().insert(unix_socket.to_owned());
+        }
+        if http_request.extensions().get::<Cancellation>().is_none() {
+            http_request
+                .extensions_mut()
+                .insert(self.cancellation.renew());
+        }
+
+        Ok(http_request)
+    }
+
+    fn send_config(&self) -> Config {
+        // forwarded headers are set in prepare, before the Host is rewritten.
+        Config {
+            forwarded: None,
+            ..self.config.as_ref().to_owned()
+        }
+    }
+}
+
+//
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<AxumResponse, Infallible>> + Send>>;
+
+impl<C> Service<HttpRequest<AxumBody>> for ReverseProxy<C>
+where
+    C: RequestSender + Clone + 'static,
+    C::Error: Into<SendError>,
+{
+    type Response = AxumResponse;
+    type Error = Infallible;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, http_request: HttpRequest<AxumBody>) -> Self::Future {
+        let proxy = self.to_owned();
+
+        Box::pin(async move {
+            let http_request = match proxy.prepare(http_request).await {
+                Ok(x) => x,
+                Err(err) => return Ok(err.into_response()),
+            };
+            let config = proxy.send_config();
+            match proxy.client.send_with_config(http_request, &config).await {
+                Ok(mut response) => {
+                    if let Some(response_rewrite) = &proxy.response_rewrite {
+                        response_rewrite.apply(&mut response);
+                    }
+                    Ok(response)
+                }
+                Err(err) => Ok(err.into().into_response()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::forwarded::{ForwardedMode, X_FORWARDED_HOST, X_FORWARDED_PROTO};
+
+    #[tokio::test]
+    async fn test_prepare() {
+        let proxy = ReverseProxy::new((), "https://backend:8443/v1/".parse().unwrap())
+            .strip_prefix("/api/");
+
+        let request = HttpRequest::builder()
+            .uri("/api/users?id=1")
+            .header("host", "example.com")
+            .body(AxumBody::empty())
+            .unwrap();
+        let request = proxy.prepare(request).await.unwrap();
+        assert_eq!(request.uri(), "https://backend:8443/v1/users?id=1");
+        assert_eq!(request.headers().get("host").unwrap(), "backend:8443");
+        assert!(!request
+            .extensions()
+            .get::<Cancellation>()
+            .unwrap()
+            .is_cancelled());
+
+        let request = HttpRequest::builder()
+            .uri("/apix")
+            .header("host", "example.com")
+            .body(AxumBody::empty())
+            .unwrap();
+        let request = proxy
+            .to_owned()
+            .preserve_host(true)
+            .prepare(request)
+            .await
+            .unwrap();
+        assert_eq!(request.uri(), "https://backend:8443/v1/apix");
+        assert_eq!(request.headers().get("host").unwrap(), "example.com");
+
+        // An h2 request without `Host`, forwarded headers describe the client's URI.
+        let request = HttpRequest::builder()
+            .uri("http://example.com/api/users")
+            .body(AxumBody::empty())
+            .unwrap();
+        let request = proxy
+            .config(Config {
+                forwarded: Some(ForwardedMode::Replace),
+                ..Default::default()
+            })
+            .prepare(request)
+            .await
+            .unwrap();
+        assert_eq!(request.uri(), "https://backend:8443/v1/users");
+        assert_eq!(
+            request.headers().get(X_FORWARDED_HOST).unwrap(),
+            "example.com"
+        );
+        assert_eq!(request.headers().get(X_FORWARDED_PROTO).unwrap(), "http");
+    }
+
+    #[cfg(feature = "impl_reqwest")]
+    #[tokio::test]
+    async fn test_nest_service() -> Result<(), Box<dyn std::error::Error>> {
+        use std::net::SocketAddr;
+
+        use axum::{http::StatusCode, routing::get, Router, Server};
+
+        //
+        let backend_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+        let server_listen_addr = SocketAddr::from((
+            [127, 0, 0, 1],
+            portpicker::pick_unused_port().expect("No ports free"),
+        ));
+
+        //
+        let backend_task = tokio::task::spawn(async move {
+            let app = Router::new().route(
+                "/users",
+                get(|request: HttpRequest<AxumBody>| async move {
+                    format!(
+                        "{} {}",
+                        request.uri(),
+                        request.headers().get("host").unwrap().to_str().unwrap()
+                    )
+                }),
+            );
+
+            let server = Server::bind(&backend_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("backend start failed");
+        });
+
+        //
+        let server_task = tokio::task::spawn(async move {
+            let proxy = ReverseProxy::new(
+                reqwest::Client::new(),
+                format!("http://{backend_listen_addr}").parse().unwrap(),
+            );
+            let unavailable = ReverseProxy::new(
+                reqwest::Client::new(),
+                "http://127.0.0.1:1".parse().unwrap(),
+            );
+            let app = Router::new()
+                .nest_service("/api", proxy)
+                .nest_service("/unavailable", unavailable);
+
+            let server = Server::bind(&server_listen_addr).serve(app.into_make_service());
+
+            server.await.expect("server start failed");
+        });
+
+        //
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+
+        //
+        let resp = reqwest::get(format!("http://{server_listen_addr}/api/users?id=1")).await?;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.text().await.unwrap(),
+            format!("/users?id=1 {backend_listen_addr}")
+        );
+
+        let resp = reqwest::get(format!("http://{server_listen_addr}/unavailable/")).await?;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+
+        //
+        server_task.abort();
+        assert!(server_task.await.unwrap_err().is_cancelled());
+
+        backend_task.abort();
+        assert!(backend_task.await.unwrap_err().is_cancelled());
+
+        Ok(())
+    }
+}
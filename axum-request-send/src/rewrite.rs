@@ -0,0 +1,415 @@
+use axum::{
+    extract::{FromRequestParts as _, MatchedPath, RawPathParams},
+    http::{
+        request::Parts as HttpRequestParts,
+        uri::{Authority, PathAndQuery, Scheme},
+        Request as HttpRequest, Uri,
+    },
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+
+use crate::error::SendError;
+
+// Ref https://url.spec.whatwg.org/#path-percent-encode-set
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/')
+    .add(b'%');
+
+//
+/// Rewrites the request URI before it is sent upstream.
+///
+/// Applied in order: `strip_prefix`, the path rewrite (`regex` or `template`),
+/// `add_prefix`, the query rules, then `scheme` and `authority`.
+#[derive(Debug, Clone, Default)]
+pub struct UriRewrite {
+    scheme: Option<Scheme>,
+    authority: Option<Authority>,
+    strip_prefix: Option<String>,
+    add_prefix: Option<String>,
+    path: Option<PathRewrite>,
+    query: Vec<QueryRule>,
+}
+
+#[derive(Debug, Clone)]
+enum PathRewrite {
+    Regex(Regex, String),
+    Template(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum QueryRule {
+    Add(String, String),
+    Remove(String),
+    Rename(String, String),
+}
+
+impl UriRewrite {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scheme and authority of `upstream`, its path (if any) is used as `add_prefix`.
+    pub fn upstream(mut self, upstream: Uri) -> Self {
+        let parts = upstream.into_parts();
+        self.scheme = Some(parts.scheme.unwrap_or(Scheme::HTTP));
+        self.authority = parts.authority;
+        self.add_prefix = parts
+            .path_and_query
+            .map(|x| x.path().trim_end_matches('/').to_owned())
+            .filter(|x| !x.is_empty());
+        self
+    }
+
+    pub fn scheme(mut self, scheme: Scheme) -> Self {
+        self.scheme = Some(scheme);
+        self
+    }
+
+    pub fn authority(mut self, authority: Authority) -> Self {
+        self.authority = Some(authority);
+        self
+    }
+
+    pub fn strip_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.strip_prefix = Some(prefix.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    pub fn add_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.add_prefix = Some(prefix.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Replaces the first match in the path, see `Regex::replace` for `$1` / `${name}`.
+    /// `{name}` placeholders (not preceded by `$`) are expanded as in `template`,
+    /// a `$` in their values is kept literally.
+    pub fn regex(mut self, regex: Regex, replacement: impl Into<String>) -> Self {
+        self.path = Some(PathRewrite::Regex(regex, replacement.into()));
+        self
+    }
+
+    /// Replaces the whole path, e.g. `/v2/users/{user_id}`.
+    ///
+    /// `{name}` is the `name` parameter of the matched route (as in `axum::extract::Path`),
+    /// `{matched_path}` the route itself (as in `MatchedPath`).
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.path = Some(PathRewrite::Template(template.into()));
+        self
+    }
+
+    pub fn add_query(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push(QueryRule::Add(name.into(), value.into()));
+        self
+    }
+
+    pub fn remove_query(mut self, name: impl Into<String>) -> Self {
+        self.query.push(QueryRule::Remove(name.into()));
+        self
+    }
+
+    pub fn rename_query(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.query.push(QueryRule::Rename(from.into(), to.into()));
+        self
+    }
+
+    pub(crate) fn target_authority(&self) -> Option<&Authority> {
+        self.authority.as_ref()
+    }
+
+    //
+    pub async fn apply<B>(
+        &self,
+        http_request: HttpRequest<B>,
+    ) -> Result<HttpRequest<B>, SendError> {
+        let (mut parts, body) = http_request.into_parts();
+        self.apply_to_parts(&mut parts).await?;
+        Ok(HttpRequest::from_parts(parts, body))
+    }
+
+    pub async fn apply_to_parts(&self, parts: &mut HttpRequestParts) -> Result<(), SendError> {
+        let params = match &self.path {
+            Some(_) => RawPathParams::from_request_parts(parts, &())
+                .await
+                .map(|params| {
+                    params
+                        .iter()
+                        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default(),
+            None => vec![],
+        };
+        let matched_path = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(|x| x.as_str().to_owned());
+
+        let uri = &parts.uri;
+        let path = uri.path();
+        let path = match &self.strip_prefix {
+            Some(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some(x) if x.is_empty() || x.starts_with('/') => x,
+                _ => path,
+            },
+            None => path,
+        };
+        let path = match &self.path {
+            Some(PathRewrite::Regex(regex, replacement)) => {
+                let replacement = expand(replacement, &params, matched_path.as_deref(), true)?;
+                regex.replace(path, replacement.as_str()).into_owned()
+            }
+            Some(PathRewrite::Template(template)) => {
+                expand(template, &params, matched_path.as_deref(), false)?
+            }
+            None => path.to_owned(),
+        };
+        let path = match &self.add_prefix {
+            Some(prefix) => format!("{prefix}{path}"),
+            None => path,
+        };
+        let path = if path.starts_with('/') {
+            path
+        } else {
+            format!("/{path}")
+        };
+        let path_and_query = match rewrite_query(uri.query(), &self.query) {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+
+        let mut uri_parts = uri.to_owned().into_parts();
+        if let Some(scheme) = &self.scheme {
+            uri_parts.scheme = Some(scheme.to_owned());
+        }
+        if let Some(authority) = &self.authority {
+            uri_parts.authority = Some(authority.to_owned());
+            uri_parts.scheme.get_or_insert(Scheme::HTTP);
+        }
+        uri_parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+        parts.uri = Uri::from_parts(uri_parts).map_err(|err| SendError::InvalidUri(err.into()))?;
+
+        Ok(())
+    }
+}
+
+//
+/// With `regex`, `${..}` is left to `Regex::replace` and `$` in values is escaped as `$$`.
+fn expand(
+    template: &str,
+    params: &[(String, String)],
+    matched_path: Option<&str>,
+    regex: bool,
+) -> Result<String, SendError> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|x| start + x)
+            .ok_or_else(|| SendError::InvalidUri(format!("unclosed {{ in {template}").into()))?;
+        expanded.push_str(&rest[..start]);
+
+        // `$$` is a literal `$`, an odd count makes it a capture group.
+        let dollars = rest[..start].len() - rest[..start].trim_end_matches('$').len();
+        if regex && dollars % 2 == 1 {
+            expanded.push_str(&rest[start..=end]);
+            rest = &rest[end + 1..];
+            continue;
+        }
+
+        let name = &rest[start + 1..end];
+        let value = match params.iter().find(|(k, _)| k == name) {
+            Some((_, value)) => {
+                // A wildcard value keeps its `/`.
+                let mut encoded = String::with_capacity(value.len());
+                for (i, segment) in value.split('/').enumerate() {
+                    if i > 0 {
+                        encoded.push('/');
+                    }
+                    encoded.extend(utf8_percent_encode(segment, PATH_SEGMENT));
+                }
+                encoded
+            }
+            None if name == "matched_path" => matched_path.unwrap_or_default().to_owned(),
+            None => {
+                return Err(SendError::InvalidUri(
+                    format!("missing path parameter {name}").into(),
+                ))
+            }
+        };
+        if regex {
+            expanded.push_str(&value.replace('$', "$$"));
+        } else {
+            expanded.push_str(&value);
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Untouched pairs keep their original encoding.
+fn rewrite_query(query: Option<&str>, rules: &[QueryRule]) -> Option<String> {
+    if rules.is_empty() {
+        return query.map(ToOwned::to_owned);
+    }
+
+    let mut pairs = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let name = form_urlencoded::parse(x.as_bytes())
+                .next()
+                .map(|(k, _)| k.into_owned())
+                .unwrap_or_default();
+            (name, x.to_owned())
+        })
+        .collect::<Vec<_>>();
+
+    for rule in rules {
+        match rule {
+            QueryRule::Add(name, value) => {
+                let pair = form_urlencoded::Serializer::new(String::new())
+                    .append_pair(name, value)
+                    .finish();
+                pairs.push((name.to_owned(), pair));
+            }
+            QueryRule::Remove(name) => pairs.retain(|(k, _)| k != name),
+            QueryRule::Rename(from, to) => {
+                for (k, pair) in pairs.iter_mut().filter(|(k, _)| k == from) {
+                    let value = pair.split_once('=').map(|(_, v)| v);
+                    let mut renamed =
+                        form_urlencoded::byte_serialize(to.as_bytes()).collect::<String>();
+                    if let Some(value) = value {
+                        renamed.push('=');
+                        renamed.push_str(value);
+                    }
+                    *k = to.to_owned();
+                    *pair = renamed;
+                }
+            }
+        }
+    }
+
+    if pairs.is_empty() {
+        None
+    } else {
+        Some(
+            pairs
+                .into_iter()
+                .map(|(_, pair)| pair)
+                .collect::<Vec<_>>()
+                .join("&"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{body::Body as AxumBody, routing::get, Router};
+    use tower_service::Service as _;
+
+    #[test]
+    fn test_rewrite_query() {
+        let rules = vec![
+            QueryRule::Remove("debug".into()),
+            QueryRule::Rename("q".into(), "query".into()),
+            QueryRule::Add("api key".into(), "a&b".into()),
+        ];
+        assert_eq!(
+            rewrite_query(Some("q=foo%20bar&debug=1&page=2&debug"), &rules).unwrap(),
+            "query=foo%20bar&page=2&api+key=a%26b"
+        );
+        assert_eq!(rewrite_query(None, &rules[..1]), None);
+        assert_eq!(rewrite_query(Some("x=1"), &[]).unwrap(), "x=1");
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let rewrite = UriRewrite::new()
+            .upstream("https://backend:8443/v1".parse().unwrap())
+            .strip_prefix("/api")
+            .remove_query("token");
+        let request = HttpRequest::builder()
+            .uri("/api/users?id=1&token=x")
+            .body(())
+            .unwrap();
+        let request = rewrite.apply(request).await.unwrap();
+        assert_eq!(request.uri(), "https://backend:8443/v1/users?id=1");
+
+        let rewrite = UriRewrite::new().regex(Regex::new("^/old/(.*)$").unwrap(), "/new/$1");
+        let request = HttpRequest::builder()
+            .uri("http://example.com/old/a/b")
+            .body(())
+            .unwrap();
+        let request = rewrite.apply(request).await.unwrap();
+        assert_eq!(request.uri(), "http://example.com/new/a/b");
+
+        let rewrite = UriRewrite::new().regex(
+            Regex::new("^/old/(?P<rest>.*)$").unwrap(),
+            "/new/${1}x/${rest}",
+        );
+        let request = HttpRequest::builder()
+            .uri("http://example.com/old/a")
+            .body(())
+            .unwrap();
+        let request = rewrite.apply(request).await.unwrap();
+        assert_eq!(request.uri(), "http://example.com/new/ax/a");
+    }
+
+    #[test]
+    fn test_expand() {
+        let params = [("id".to_owned(), "a$1".to_owned())];
+        assert_eq!(
+            expand("/u/{id}/${1}/$${id}", &params, None, true).unwrap(),
+            "/u/a$$1/${1}/$$a$$1"
+        );
+        assert_eq!(expand("/u/{id}", &params, None, false).unwrap(), "/u/a$1");
+        assert!(expand("/u/${1}", &params, None, false).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_template() {
+        let rewrite = UriRewrite::new()
+            .authority(Authority::from_static("users.internal"))
+            .template("/v2/users/{user_id}/{rest}")
+            .add_query("from", "proxy");
+        let matched_path = UriRewrite::new().template("/v2{matched_path}");
+        let mut router: Router = Router::new().route(
+            "/users/:user_id/*rest",
+            get(move |request: HttpRequest<AxumBody>| async move {
+                let (mut parts, _) = request.into_parts();
+                let uri = parts.uri.to_owned();
+                rewrite.apply_to_parts(&mut parts).await.unwrap();
+                let rewritten = parts.uri.to_string();
+                parts.uri = uri;
+                matched_path.apply_to_parts(&mut parts).await.unwrap();
+                format!("{rewritten} {}", parts.uri)
+            }),
+        );
+
+        let request = HttpRequest::builder()
+            .uri("/users/a%20b/posts/1?x=1")
+            .body(AxumBody::empty())
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            "http://users.internal/v2/users/a%20b/posts/1?x=1&from=proxy /v2/users/:user_id/*rest?x=1"
+        );
+    }
+}
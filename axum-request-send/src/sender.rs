@@ -0,0 +1,158 @@
+use core::{future::Future, pin::Pin};
+use std::sync::Arc;
+
+use axum::{
+    body::Body as AxumBody, http::Request as HttpRequest, response::Response as AxumResponse,
+};
+
+use crate::{error::SendError, Config};
+
+//
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+//
+/// Implemented for `reqwest::Client` (`impl_reqwest`), `isahc::HttpClient` (`impl_isahc`)
+/// and `hyper::Client` (`impl_hyper`).
+pub trait RequestSender: Send + Sync {
+    type Error;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>>;
+
+    fn send(
+        &self,
+        http_request: HttpRequest<AxumBody>,
+    ) -> BoxFuture<'_, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            let config = Config::default();
+            self.send_with_config(http_request, &config).await
+        })
+    }
+}
+
+impl<S> RequestSender for Arc<S>
+where
+    S: RequestSender + ?Sized,
+{
+    type Error = S::Error;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        self.as_ref().send_with_config(http_request, config)
+    }
+}
+
+//
+/// A type-erased `RequestSender`, e.g. for holding a configured sender in axum state.
+#[derive(Clone)]
+pub struct BoxRequestSender(Arc<dyn RequestSender<Error = SendError>>);
+
+impl BoxRequestSender {
+    pub fn new<S>(sender: S) -> Self
+    where
+        S: RequestSender + 'static,
+        S::Error: Into<SendError>,
+    {
+        Self(Arc::new(MapErr(sender)))
+    }
+}
+
+impl core::fmt::Debug for BoxRequestSender {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("BoxRequestSender").finish()
+    }
+}
+
+impl RequestSender for BoxRequestSender {
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        self.0.send_with_config(http_request, config)
+    }
+}
+
+struct MapErr<S>(S);
+
+impl<S> RequestSender for MapErr<S>
+where
+    S: RequestSender,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            self.0
+                .send_with_config(http_request, config)
+                .await
+                .map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::StatusCode;
+
+    struct Echo;
+
+    impl RequestSender for Echo {
+        type Error = std::io::Error;
+
+        fn send_with_config<'a>(
+            &'a self,
+            http_request: HttpRequest<AxumBody>,
+            config: &'a Config,
+        ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+            Box::pin(async move {
+                if http_request.uri() == "/error" {
+                    return Err(std::io::Error::other("error"));
+                }
+                let mut response = AxumResponse::new(axum::body::boxed(AxumBody::empty()));
+                if config.strip_hop_by_hop_headers {
+                    *response.status_mut() = StatusCode::NO_CONTENT;
+                }
+                Ok(response)
+            })
+        }
+    }
+
+    async fn send_generic<S: RequestSender>(
+        sender: &S,
+        uri: &str,
+    ) -> Result<AxumResponse, S::Error> {
+        let request = HttpRequest::builder()
+            .uri(uri)
+            .body(AxumBody::empty())
+            .unwrap();
+        sender.send(request).await
+    }
+
+    #[tokio::test]
+    async fn test_box_request_sender() {
+        let sender = BoxRequestSender::new(Echo);
+
+        let response = send_generic(&sender, "/").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let err = send_generic(&sender.clone(), "/error").await.unwrap_err();
+        assert!(matches!(err, SendError::BodyStream(_)));
+    }
+}
@@ -0,0 +1,363 @@
+use core::{
+    future::Future as _,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{Body as AxumBody, BoxBody, Bytes, HttpBody},
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Request as HttpRequest},
+    response::Response as AxumResponse,
+};
+use http_body::SizeHint;
+use tokio::time::{Instant, Sleep};
+
+use crate::{
+    error::SendError,
+    sender::{BoxFuture, RequestSender},
+    Config,
+};
+
+//
+pub const GRPC_TIMEOUT: &str = "grpc-timeout";
+/// Absolute deadline, in milliseconds since the unix epoch.
+pub const X_REQUEST_DEADLINE: &str = "x-request-deadline";
+
+//
+/// Per-call timeout, insert it into the request extensions.
+/// Takes precedence over `Timeout::timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeout(pub Duration);
+
+//
+/// Enforces a deadline on connect, time to first byte and the whole response body.
+///
+/// When `deadline_headers` is enabled, the deadline is also derived from the incoming
+/// `grpc-timeout` / `X-Request-Deadline`, and forwarded to the upstream with
+/// the remaining time.
+#[derive(Debug, Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    deadline_headers: bool,
+}
+
+impl<S> Timeout<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            timeout: None,
+            deadline_headers: false,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn deadline_headers(mut self, deadline_headers: bool) -> Self {
+        self.deadline_headers = deadline_headers;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn deadline<B>(&self, http_request: &HttpRequest<B>) -> Option<Instant> {
+        let now = Instant::now();
+        let timeout = http_request
+            .extensions()
+            .get::<RequestTimeout>()
+            .map(|RequestTimeout(x)| *x)
+            .or(self.timeout);
+        let from_headers = if self.deadline_headers {
+            timeout_from_headers(http_request.headers())
+        } else {
+            None
+        };
+
+        match (timeout, from_headers) {
+            (Some(a), Some(b)) => Some(now + a.min(b)),
+            (Some(x), None) | (None, Some(x)) => Some(now + x),
+            (None, None) => None,
+        }
+    }
+}
+
+impl<S> RequestSender for Timeout<S>
+where
+    S: RequestSender,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        mut http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            let deadline = match self.deadline(&http_request) {
+                Some(x) => x,
+                None => {
+                    return self
+                        .inner
+                        .send_with_config(http_request, config)
+                        .await
+                        .map_err(Into::into)
+                }
+            };
+
+            if self.deadline_headers {
+                set_deadline_headers(
+                    http_request.headers_mut(),
+                    deadline.saturating_duration_since(Instant::now()),
+                );
+            }
+
+            let response = tokio::time::timeout_at(
+                deadline,
+                self.inner.send_with_config(http_request, config),
+            )
+            .await
+            .map_err(|err| SendError::Timeout(err.into()))?
+            .map_err(Into::into)?;
+
+            Ok(response.map(|body| axum::body::boxed(DeadlineBody::new(body, deadline))))
+        })
+    }
+}
+
+//
+/// Fails with a timeout error once `deadline` is reached, while the body is still streaming.
+pub struct DeadlineBody {
+    inner: BoxBody,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl DeadlineBody {
+    pub fn new(inner: BoxBody, deadline: Instant) -> Self {
+        Self {
+            inner,
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+        }
+    }
+
+    fn elapsed() -> axum::Error {
+        axum::Error::new(IoError::new(
+            IoErrorKind::TimedOut,
+            "deadline elapsed while streaming the body",
+        ))
+    }
+}
+
+impl HttpBody for DeadlineBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Pending if self.sleep.as_mut().poll(cx).is_ready() => {
+                Poll::Ready(Some(Err(Self::elapsed())))
+            }
+            poll => poll,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match Pin::new(&mut self.inner).poll_trailers(cx) {
+            Poll::Pending if self.sleep.as_mut().poll(cx).is_ready() => {
+                Poll::Ready(Err(Self::elapsed()))
+            }
+            poll => poll,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+//
+pub fn timeout_from_headers(headers: &HeaderMap) -> Option<Duration> {
+    let grpc_timeout = headers
+        .get(GRPC_TIMEOUT)
+        .and_then(|x| x.to_str().ok())
+        .and_then(parse_grpc_timeout);
+    let request_deadline = headers
+        .get(X_REQUEST_DEADLINE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .map(|x| {
+            let deadline = UNIX_EPOCH + Duration::from_millis(x);
+            deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+        });
+
+    match (grpc_timeout, request_deadline) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (Some(x), None) | (None, Some(x)) => Some(x),
+        (None, None) => None,
+    }
+}
+
+pub fn set_deadline_headers(headers: &mut HeaderMap, remaining: Duration) {
+    let is_grpc = headers
+        .get(CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.starts_with("application/grpc"))
+        .unwrap_or(false);
+    if is_grpc || headers.contains_key(GRPC_TIMEOUT) {
+        if let Ok(value) = HeaderValue::from_str(&format_grpc_timeout(remaining)) {
+            headers.insert(GRPC_TIMEOUT, value);
+        }
+    }
+
+    let deadline = SystemTime::now() + remaining;
+    if let Ok(x) = deadline.duration_since(UNIX_EPOCH) {
+        headers.insert(X_REQUEST_DEADLINE, HeaderValue::from(x.as_millis() as u64));
+    }
+}
+
+// Ref https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (n, unit) = value.split_at(value.len() - 1);
+    let n = n.parse::<u64>().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(n.saturating_mul(60 * 60))),
+        "M" => Some(Duration::from_secs(n.saturating_mul(60))),
+        "S" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_millis(n)),
+        "u" => Some(Duration::from_micros(n)),
+        "n" => Some(Duration::from_nanos(n)),
+        _ => None,
+    }
+}
+
+pub fn format_grpc_timeout(timeout: Duration) -> String {
+    const MAX: u128 = 99_999_999;
+
+    let millis = timeout.as_millis();
+    if millis <= MAX {
+        format!("{millis}m")
+    } else {
+        format!("{}S", timeout.as_secs().min(MAX as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{http::StatusCode, routing::get, Router};
+
+    use crate::impl_service::ServiceSender;
+
+    #[test]
+    fn test_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_grpc_timeout("100"), None);
+        assert_eq!(parse_grpc_timeout("123456789m"), None);
+
+        assert_eq!(format_grpc_timeout(Duration::from_millis(1500)), "1500m");
+        assert_eq!(format_grpc_timeout(Duration::from_secs(200_000)), "200000S");
+    }
+
+    #[tokio::test]
+    async fn test_send() -> Result<(), Box<dyn std::error::Error>> {
+        let backend: Router = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    "slow"
+                }),
+            )
+            .route(
+                "/slow_body",
+                get(|| async {
+                    let (mut body_tx, body) = AxumBody::channel();
+                    tokio::task::spawn(async move {
+                        body_tx.send_data(Bytes::from_static(b"foo")).await.ok();
+                        tokio::time::sleep(Duration::from_millis(500)).await;
+                        body_tx.send_data(Bytes::from_static(b"bar")).await.ok();
+                    });
+                    axum::body::StreamBody::new(body)
+                }),
+            )
+            .route(
+                "/deadline",
+                get(|request: HttpRequest<AxumBody>| async move {
+                    let headers = request.headers();
+                    let grpc_timeout = headers.get(GRPC_TIMEOUT).unwrap().to_str().unwrap();
+                    let grpc_timeout = parse_grpc_timeout(grpc_timeout).unwrap();
+                    assert!(grpc_timeout <= Duration::from_millis(200));
+                    assert!(headers.contains_key(X_REQUEST_DEADLINE));
+                    "ok"
+                }),
+            );
+        let sender = Timeout::new(ServiceSender::new(backend))
+            .timeout(Duration::from_millis(200))
+            .deadline_headers(true);
+
+        //
+        let request = HttpRequest::builder()
+            .uri("/slow")
+            .body(AxumBody::empty())?;
+        let err = sender.send(request).await.unwrap_err();
+        assert!(err.is_timeout());
+
+        //
+        let mut request = HttpRequest::builder()
+            .uri("/slow")
+            .body(AxumBody::empty())?;
+        request
+            .extensions_mut()
+            .insert(RequestTimeout(Duration::from_secs(2)));
+        let response = sender.send(request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "slow");
+
+        //
+        let request = HttpRequest::builder()
+            .uri("/slow_body")
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(hyper::body::to_bytes(response.into_body()).await.is_err());
+
+        //
+        let request = HttpRequest::builder()
+            .uri("/deadline")
+            .header(GRPC_TIMEOUT, "10S")
+            .body(AxumBody::empty())?;
+        let response = sender.send(request).await?;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await?, "ok");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+use core::str::FromStr;
+use std::path::{Path, PathBuf};
+
+use crate::error::SendError;
+
+//
+/// A Unix domain socket upstream, e.g. `unix:/run/app.sock`.
+///
+/// Set it as a request extension (or with `ReverseProxy::unix_socket`) to send through it,
+/// the request path and `Host` are kept as they are.
+///
+/// Supported by `impl_isahc` and by `impl_hyper` with a client built on `UnixConnector`.
+/// `impl_reqwest` fails such requests with `SendError::InvalidUri`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnixSocket(PathBuf);
+
+impl UnixSocket {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self(path.into())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl FromStr for UnixSocket {
+    type Err = SendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) if !path.is_empty() => Ok(Self::new(path)),
+            _ => Err(SendError::InvalidUri(
+                format!("{s} is not a unix:<path> address").into(),
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for UnixSocket {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "unix:{}", self.0.display())
+    }
+}
+
+//
+#[cfg(all(unix, feature = "impl_hyper"))]
+pub(crate) use self::connector::set_socket_authority;
+#[cfg(all(unix, feature = "impl_hyper"))]
+pub use self::connector::{UnixConnector, UnixOrStream};
+
+#[cfg(all(unix, feature = "impl_hyper"))]
+mod connector {
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use std::{
+        io::{Error as IoError, IoSlice},
+        path::PathBuf,
+        sync::OnceLock,
+    };
+
+    use axum::http::{
+        header::HOST,
+        uri::{Authority, PathAndQuery, Scheme},
+        HeaderValue, Request as HttpRequest, Uri,
+    };
+    use hyper::client::{
+        connect::{Connected, Connection},
+        HttpConnector,
+    };
+    use tokio::{
+        io::{AsyncRead, AsyncWrite, ReadBuf},
+        net::UnixStream,
+    };
+    use tower_service::Service;
+
+    use super::UnixSocket;
+    use crate::sender::{BoxError, BoxFuture};
+
+    /// `.invalid` never resolves (RFC 6761), and the per-process key
+    /// keeps a request URI from naming a socket itself.
+    fn authority_suffix() -> &'static str {
+        static SUFFIX: OnceLock<String> = OnceLock::new();
+        SUFFIX.get_or_init(|| format!(".{:016x}.sock.invalid", rand::random::<u64>()))
+    }
+
+    /// Moves the socket into the URI authority (hex encoded) for `UnixConnector`,
+    /// the original authority is kept as `Host`.
+    pub(crate) fn set_socket_authority<B>(http_request: &mut HttpRequest<B>, socket: &UnixSocket) {
+        use std::os::unix::ffi::OsStrExt as _;
+
+        if !http_request.headers().contains_key(HOST) {
+            if let Some(authority) = http_request.uri().authority() {
+                if let Ok(value) = HeaderValue::from_str(authority.as_str()) {
+                    http_request.headers_mut().insert(HOST, value);
+                }
+            }
+        }
+
+        let authority = format!(
+            "{}{}",
+            hex::encode(socket.path().as_os_str().as_bytes()),
+            authority_suffix()
+        );
+        let mut parts = http_request.uri().to_owned().into_parts();
+        parts.scheme = Some(Scheme::HTTP);
+        parts.authority = Authority::try_from(authority).ok();
+        parts
+            .path_and_query
+            .get_or_insert(PathAndQuery::from_static("/"));
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *http_request.uri_mut() = uri;
+        }
+    }
+
+    pub(super) fn socket_path(uri: &Uri) -> Option<PathBuf> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt as _};
+
+        let hex = uri.host()?.strip_suffix(authority_suffix())?;
+        let bytes = hex::decode(hex).ok()?;
+        Some(PathBuf::from(OsStr::from_bytes(&bytes)))
+    }
+
+    //
+    /// A `hyper` connector for requests carrying a `UnixSocket`, other requests go to `inner`.
+    ///
+    /// `hyper::Client::builder().build(UnixConnector::default())`
+    #[derive(Debug, Clone)]
+    pub struct UnixConnector<C = HttpConnector> {
+        inner: C,
+    }
+
+    impl<C> UnixConnector<C> {
+        pub fn new(inner: C) -> Self {
+            Self { inner }
+        }
+    }
+
+    impl Default for UnixConnector {
+        fn default() -> Self {
+            Self::new(HttpConnector::new())
+        }
+    }
+
+    impl<C> Service<Uri> for UnixConnector<C>
+    where
+        C: Service<Uri>,
+        C::Future: Send + 'static,
+        C::Error: Into<BoxError>,
+    {
+        type Response = UnixOrStream<C::Response>;
+        type Error = BoxError;
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx).map_err(Into::into)
+        }
+
+        fn call(&mut self, uri: Uri) -> Self::Future {
+            match socket_path(&uri) {
+                Some(path) => Box::pin(async move {
+                    let stream = UnixStream::connect(path).await?;
+                    Ok(UnixOrStream::Unix(stream))
+                }),
+                None => {
+                    let connecting = self.inner.call(uri);
+                    Box::pin(async move {
+                        connecting
+                            .await
+                            .map(UnixOrStream::Other)
+                            .map_err(Into::into)
+                    })
+                }
+            }
+        }
+    }
+
+    //
+    #[derive(Debug)]
+    pub enum UnixOrStream<T> {
+        Unix(UnixStream),
+        Other(T),
+    }
+
+    impl<T> Connection for UnixOrStream<T>
+    where
+        T: Connection,
+    {
+        fn connected(&self) -> Connected {
+            match self {
+                Self::Unix(_) => Connected::new(),
+                Self::Other(x) => x.connected(),
+            }
+        }
+    }
+
+    impl<T> AsyncRead for UnixOrStream<T>
+    where
+        T: AsyncRead + Unpin,
+    {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<Result<(), IoError>> {
+            match self.get_mut() {
+                Self::Unix(x) => Pin::new(x).poll_read(cx, buf),
+                Self::Other(x) => Pin::new(x).poll_read(cx, buf),
+            }
+        }
+    }
+
+    impl<T> AsyncWrite for UnixOrStream<T>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, IoError>> {
+            match self.get_mut() {
+                Self::Unix(x) => Pin::new(x).poll_write(cx, buf),
+                Self::Other(x) => Pin::new(x).poll_write(cx, buf),
+            }
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<Result<usize, IoError>> {
+            match self.get_mut() {
+                Self::Unix(x) => Pin::new(x).poll_write_vectored(cx, bufs),
+                Self::Other(x) => Pin::new(x).poll_write_vectored(cx, bufs),
+            }
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            match self {
+                Self::Unix(x) => x.is_write_vectored(),
+                Self::Other(x) => x.is_write_vectored(),
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            match self.get_mut() {
+                Self::Unix(x) => Pin::new(x).poll_flush(cx),
+                Self::Other(x) => Pin::new(x).poll_flush(cx),
+            }
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            match self.get_mut() {
+                Self::Unix(x) => Pin::new(x).poll_shutdown(cx),
+                Self::Other(x) => Pin::new(x).poll_shutdown(cx),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        let socket = "unix:/run/app.sock".parse::<UnixSocket>().unwrap();
+        assert_eq!(socket.path(), Path::new("/run/app.sock"));
+        assert_eq!(socket.to_string(), "unix:/run/app.sock");
+        assert!("unix:".parse::<UnixSocket>().is_err());
+        assert!("http://localhost".parse::<UnixSocket>().is_err());
+    }
+
+    #[cfg(all(unix, feature = "impl_hyper"))]
+    #[test]
+    fn test_set_socket_authority() {
+        use axum::http::{header::HOST, Request as HttpRequest};
+
+        use super::connector::{set_socket_authority, socket_path};
+
+        let socket = UnixSocket::new("/run/app.sock");
+        let mut request = HttpRequest::builder()
+            .uri("http://app.internal/users?id=1")
+            .body(())
+            .unwrap();
+        set_socket_authority(&mut request, &socket);
+        assert_eq!(request.headers().get(HOST).unwrap(), "app.internal");
+        assert_eq!(request.uri().path_and_query().unwrap(), "/users?id=1");
+        assert_eq!(
+            socket_path(request.uri()).unwrap(),
+            Path::new("/run/app.sock")
+        );
+
+        assert!(socket_path(&"http://app.internal/".parse().unwrap()).is_none());
+        assert!(socket_path(&"http://cafe.sock/".parse().unwrap()).is_none());
+        assert!(socket_path(
+            &"http://2f72756e2f6170702e736f636b.0000000000000000.sock.invalid/"
+                .parse()
+                .unwrap()
+        )
+        .is_none());
+    }
+}
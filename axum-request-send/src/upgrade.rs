@@ -0,0 +1,77 @@
+use axum::http::{
+    header::{CONNECTION, UPGRADE},
+    HeaderMap, HeaderValue, Request as HttpRequest,
+};
+
+use crate::hop_by_hop::remove_hop_by_hop_headers;
+
+//
+/// `Connection: upgrade` with an `Upgrade` header, e.g. a WebSocket handshake.
+///
+/// Only the hyper backend (and `ServiceSender`) can proxy upgrades,
+/// reqwest and isahc do not expose the upgraded connection.
+pub fn is_upgrade_request<B>(http_request: &HttpRequest<B>) -> bool {
+    is_upgrade(http_request.headers())
+}
+
+pub fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers.contains_key(UPGRADE)
+        && headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','))
+            .any(|x| x.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Like `remove_hop_by_hop_headers`, but keeps `Upgrade` and `Connection: upgrade`.
+pub fn remove_hop_by_hop_headers_keep_upgrade(headers: &mut HeaderMap) {
+    let upgrade = headers.get(UPGRADE).cloned();
+    remove_hop_by_hop_headers(headers);
+    if let Some(upgrade) = upgrade {
+        headers.insert(UPGRADE, upgrade);
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    }
+}
+
+//
+/// Copies bytes in both directions once both sides are upgraded.
+#[cfg(feature = "impl_hyper")]
+pub fn spawn_tunnel(
+    downstream: hyper::upgrade::OnUpgrade,
+    upstream: hyper::upgrade::OnUpgrade,
+) -> tokio::task::JoinHandle<Result<(u64, u64), crate::sender::BoxError>> {
+    tokio::task::spawn(async move {
+        let (mut downstream, mut upstream) = futures_util::future::try_join(downstream, upstream)
+            .await
+            .map_err(crate::sender::BoxError::from)?;
+        let copied = tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await?;
+        Ok(copied)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_hop_by_hop_headers_keep_upgrade() {
+        let mut headers = HeaderMap::new();
+        headers.insert("connection", "keep-alive, Upgrade".parse().unwrap());
+        headers.insert("upgrade", "websocket".parse().unwrap());
+        headers.insert("keep-alive", "timeout=5".parse().unwrap());
+        headers.insert(
+            "sec-websocket-key",
+            "dGhlIHNhbXBsZSBub25jZQ==".parse().unwrap(),
+        );
+        assert!(is_upgrade(&headers));
+
+        remove_hop_by_hop_headers_keep_upgrade(&mut headers);
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.get("connection").unwrap(), "upgrade");
+        assert_eq!(headers.get("upgrade").unwrap(), "websocket");
+
+        headers.remove("connection");
+        assert!(!is_upgrade(&headers));
+    }
+}
@@ -0,0 +1,590 @@
+use core::{
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Instant,
+};
+
+use axum::{
+    body::Body as AxumBody,
+    extract::{FromRequestParts as _, RawPathParams},
+    http::{
+        header::HOST,
+        uri::{Authority, Scheme},
+        HeaderName, HeaderValue, Request as HttpRequest, Uri,
+    },
+    response::Response as AxumResponse,
+};
+use rand::Rng as _;
+
+use crate::{
+    body::GuardBody,
+    error::SendError,
+    sender::{BoxFuture, RequestSender},
+    Config,
+};
+
+//
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    RoundRobin,
+    LeastInFlight,
+    /// Pick two endpoints at random, use the one with less requests in flight.
+    RandomTwoChoices,
+    ConsistentHash(HashKey),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashKey {
+    Header(HeaderName),
+    /// A path parameter of the matched route, e.g. `user_id` in `/users/:user_id`.
+    PathParam(String),
+}
+
+//
+#[derive(Debug)]
+pub struct Endpoint {
+    scheme: Scheme,
+    authority: Authority,
+    in_flight: AtomicUsize,
+    failures: AtomicUsize,
+    healthy: AtomicBool,
+    probes: AtomicUsize,
+    state: Mutex<EndpointState>,
+}
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    down_until: Option<Instant>,
+    up_since: Option<Instant>,
+}
+
+impl Endpoint {
+    fn new(uri: Uri) -> Self {
+        let parts = uri.into_parts();
+        Self {
+            scheme: parts.scheme.unwrap_or(Scheme::HTTP),
+            authority: parts
+                .authority
+                .unwrap_or_else(|| Authority::from_static("localhost")),
+            in_flight: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            probes: AtomicUsize::new(0),
+            state: Mutex::new(EndpointState::default()),
+        }
+    }
+
+    pub fn scheme(&self) -> &Scheme {
+        &self.scheme
+    }
+
+    pub fn authority(&self) -> &Authority {
+        &self.authority
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// As reported by the active health check, see `UpstreamPool::spawn_health_check`.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Marked down or unhealthy, i.e. not picked.
+    pub fn is_down(&self) -> bool {
+        !self.is_healthy()
+            || self
+                .state()
+                .down_until
+                .is_some_and(|down_until| Instant::now() < down_until)
+    }
+
+    /// Marks the endpoint down for `down_for`, e.g. from an admin endpoint.
+    pub fn mark_down(&self, down_for: Duration) {
+        let mut state = self.state();
+        state.down_until = Some(Instant::now() + down_for);
+        state.up_since = None;
+    }
+
+    pub fn mark_up(&self) {
+        let mut state = self.state();
+        if state.down_until.take().is_some() {
+            state.up_since = Some(Instant::now());
+        }
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Flips the health after `healthy_threshold` consecutive successful probes
+    /// or `unhealthy_threshold` consecutive failed probes.
+    pub(crate) fn record_probe(
+        &self,
+        success: bool,
+        healthy_threshold: usize,
+        unhealthy_threshold: usize,
+    ) {
+        if success == self.is_healthy() {
+            self.probes.store(0, Ordering::Relaxed);
+            return;
+        }
+        let threshold = if success {
+            healthy_threshold
+        } else {
+            unhealthy_threshold
+        };
+        if self.probes.fetch_add(1, Ordering::Relaxed) + 1 < threshold {
+            return;
+        }
+        self.probes.store(0, Ordering::Relaxed);
+        self.healthy.store(success, Ordering::Relaxed);
+        if success {
+            self.state().up_since = Some(Instant::now());
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, EndpointState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// `0.0` when down or unhealthy, ramps up from `0.1` to `1.0` during `slow_start`.
+    fn weight(&self, now: Instant, slow_start: Duration) -> f64 {
+        if !self.is_healthy() {
+            return 0.0;
+        }
+        let mut state = self.state();
+        match state.down_until {
+            Some(down_until) if now < down_until => return 0.0,
+            Some(down_until) => {
+                state.down_until = None;
+                state.up_since = Some(down_until);
+                self.failures.store(0, Ordering::Relaxed);
+            }
+            None => {}
+        }
+        match state.up_since {
+            Some(up_since) if !slow_start.is_zero() => {
+                let elapsed = now.saturating_duration_since(up_since);
+                if elapsed >= slow_start {
+                    state.up_since = None;
+                    1.0
+                } else {
+                    (elapsed.as_secs_f64() / slow_start.as_secs_f64()).max(0.1)
+                }
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+struct InFlightGuard(Arc<Endpoint>);
+
+impl InFlightGuard {
+    fn new(endpoint: Arc<Endpoint>) -> Self {
+        endpoint.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(endpoint)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+//
+/// Picks an endpoint per request and substitutes its scheme and authority into the request URI.
+///
+/// An endpoint is marked down for `down_for` after `max_failures` consecutive failures
+/// (send errors, 502, 503 or 504), and then takes `slow_start` to get its full weight back.
+/// Unhealthy endpoints (see `spawn_health_check`) are skipped too.
+#[derive(Debug, Clone)]
+pub struct UpstreamPool<S> {
+    inner: S,
+    pub(crate) endpoints: Arc<Vec<Arc<Endpoint>>>,
+    ring: Arc<Vec<(u64, usize)>>,
+    next: Arc<AtomicUsize>,
+    strategy: Strategy,
+    max_failures: usize,
+    down_for: Duration,
+    slow_start: Duration,
+}
+
+impl<S> UpstreamPool<S> {
+    const VIRTUAL_NODES: usize = 100;
+
+    pub fn new(inner: S, upstreams: impl IntoIterator<Item = Uri>) -> Self {
+        let endpoints = upstreams
+            .into_iter()
+            .map(|x| Arc::new(Endpoint::new(x)))
+            .collect::<Vec<_>>();
+
+        let mut ring = endpoints
+            .iter()
+            .enumerate()
+            .flat_map(|(i, endpoint)| {
+                (0..Self::VIRTUAL_NODES).map(move |n| (hash(&(endpoint.authority.as_str(), n)), i))
+            })
+            .collect::<Vec<_>>();
+        ring.sort_unstable();
+
+        Self {
+            inner,
+            endpoints: Arc::new(endpoints),
+            ring: Arc::new(ring),
+            next: Arc::new(AtomicUsize::new(0)),
+            strategy: Strategy::RoundRobin,
+            max_failures: 5,
+            down_for: Duration::from_secs(10),
+            slow_start: Duration::ZERO,
+        }
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// `0` disables passive failure detection.
+    pub fn max_failures(mut self, max_failures: usize) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    pub fn down_for(mut self, down_for: Duration) -> Self {
+        self.down_for = down_for;
+        self
+    }
+
+    pub fn slow_start(mut self, slow_start: Duration) -> Self {
+        self.slow_start = slow_start;
+        self
+    }
+
+    pub fn endpoints(&self) -> &[Arc<Endpoint>] {
+        &self.endpoints
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    //
+    pub fn pick(&self, hash_key: Option<u64>) -> Option<Arc<Endpoint>> {
+        let now = Instant::now();
+        let weights = self
+            .endpoints
+            .iter()
+            .map(|x| x.weight(now, self.slow_start))
+            .collect::<Vec<_>>();
+        if weights.iter().all(|x| *x == 0.0) {
+            return None;
+        }
+
+        let i = match (&self.strategy, hash_key) {
+            (Strategy::ConsistentHash(_), Some(hash_key)) => self.pick_hash(hash_key, &weights),
+            (Strategy::ConsistentHash(_), None) | (Strategy::RoundRobin, _) => {
+                self.pick_round_robin(&weights)
+            }
+            (Strategy::LeastInFlight, _) => self.pick_least_in_flight(&weights, 0..weights.len()),
+            (Strategy::RandomTwoChoices, _) => {
+                let a = pick_random(&weights);
+                let b = pick_random(&weights);
+                self.pick_least_in_flight(&weights, [a, b].into_iter())
+            }
+        };
+        Some(self.endpoints[i].to_owned())
+    }
+
+    fn pick_round_robin(&self, weights: &[f64]) -> usize {
+        let mut rng = rand::thread_rng();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let candidates = (0..weights.len())
+            .map(|n| (start + n) % weights.len())
+            .filter(|i| weights[*i] > 0.0);
+        let mut first = None;
+        for i in candidates {
+            first.get_or_insert(i);
+            if weights[i] >= 1.0 || rng.gen_bool(weights[i]) {
+                return i;
+            }
+        }
+        first.unwrap_or_default()
+    }
+
+    fn pick_least_in_flight(
+        &self,
+        weights: &[f64],
+        candidates: impl Iterator<Item = usize>,
+    ) -> usize {
+        candidates
+            .filter(|i| weights[*i] > 0.0)
+            .map(|i| {
+                let score = (self.endpoints[i].in_flight() + 1) as f64 / weights[i];
+                (i, score)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+            .unwrap_or_default()
+    }
+
+    fn pick_hash(&self, hash_key: u64, weights: &[f64]) -> usize {
+        let start = self.ring.partition_point(|(x, _)| *x < hash_key);
+        self.ring
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(self.ring.len())
+            .map(|(_, i)| *i)
+            .find(|i| weights[*i] > 0.0)
+            .unwrap_or_default()
+    }
+
+    async fn hash_key(
+        &self,
+        http_request: HttpRequest<AxumBody>,
+    ) -> (Option<u64>, HttpRequest<AxumBody>) {
+        match &self.strategy {
+            Strategy::ConsistentHash(HashKey::Header(name)) => {
+                let hash_key = http_request
+                    .headers()
+                    .get(name)
+                    .map(|x| hash(&x.as_bytes()));
+                (hash_key, http_request)
+            }
+            Strategy::ConsistentHash(HashKey::PathParam(name)) => {
+                let (mut parts, body) = http_request.into_parts();
+                let hash_key = RawPathParams::from_request_parts(&mut parts, &())
+                    .await
+                    .ok()
+                    .and_then(|params| {
+                        params
+                            .iter()
+                            .find(|(k, _)| k == name)
+                            .map(|(_, v)| hash(&v))
+                    });
+                (hash_key, HttpRequest::from_parts(parts, body))
+            }
+            _ => (None, http_request),
+        }
+    }
+
+    fn record(&self, endpoint: &Endpoint, failed: bool) {
+        if !failed {
+            endpoint.failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let failures = endpoint.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.max_failures > 0 && failures >= self.max_failures {
+            endpoint.mark_down(self.down_for);
+        }
+    }
+}
+
+impl<S> RequestSender for UpstreamPool<S>
+where
+    S: RequestSender,
+    S::Error: Into<SendError>,
+{
+    type Error = SendError;
+
+    fn send_with_config<'a>(
+        &'a self,
+        http_request: HttpRequest<AxumBody>,
+        config: &'a Config,
+    ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+        Box::pin(async move {
+            let (hash_key, mut http_request) = self.hash_key(http_request).await;
+            let endpoint = self.pick(hash_key).ok_or_else(|| {
+                SendError::Unavailable("no upstream endpoint is available".into())
+            })?;
+            set_endpoint(&mut http_request, &endpoint)?;
+
+            let guard = InFlightGuard::new(endpoint.to_owned());
+            let result = self
+                .inner
+                .send_with_config(http_request, config)
+                .await
+                .map_err(Into::into);
+
+            let failed = match &result {
+                Ok(response) => matches!(response.status().as_u16(), 502..=504),
+                Err(_) => true,
+            };
+            self.record(&endpoint, failed);
+
+            result
+                .map(|response| response.map(|body| axum::body::boxed(GuardBody::new(body, guard))))
+        })
+    }
+}
+
+//
+/// Substitutes the scheme and authority of `endpoint`,
+/// the `Host` is replaced too when it was the previous authority.
+fn set_endpoint(
+    http_request: &mut HttpRequest<AxumBody>,
+    endpoint: &Endpoint,
+) -> Result<(), SendError> {
+    let mut parts = http_request.uri().to_owned().into_parts();
+    let previous_authority = parts.authority.take();
+    parts.scheme = Some(endpoint.scheme.to_owned());
+    parts.authority = Some(endpoint.authority.to_owned());
+    if parts.path_and_query.is_none() {
+        parts.path_and_query = Some("/".parse()?);
+    }
+    *http_request.uri_mut() =
+        Uri::from_parts(parts).map_err(|err| SendError::InvalidUri(err.into()))?;
+
+    let headers = http_request.headers_mut();
+    let host_is_previous = match (headers.get(HOST), &previous_authority) {
+        (Some(host), Some(authority)) => host.as_bytes() == authority.as_str().as_bytes(),
+        _ => false,
+    };
+    if host_is_previous {
+        if let Ok(value) = HeaderValue::from_str(endpoint.authority.as_str()) {
+            headers.insert(HOST, value);
+        }
+    }
+    Ok(())
+}
+
+fn pick_random(weights: &[f64]) -> usize {
+    let total = weights.iter().sum::<f64>();
+    let mut n = rand::thread_rng().gen_range(0.0..total);
+    for (i, weight) in weights.iter().enumerate() {
+        if n < *weight {
+            return i;
+        }
+        n -= weight;
+    }
+    weights.iter().rposition(|x| *x > 0.0).unwrap_or_default()
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::StatusCode;
+
+    /// Responds with the authority, `503` for `bad.internal`.
+    struct Echo;
+
+    impl RequestSender for Echo {
+        type Error = SendError;
+
+        fn send_with_config<'a>(
+            &'a self,
+            http_request: HttpRequest<AxumBody>,
+            _config: &'a Config,
+        ) -> BoxFuture<'a, Result<AxumResponse, Self::Error>> {
+            Box::pin(async move {
+                let authority = http_request.uri().authority().unwrap().to_string();
+                let mut response =
+                    AxumResponse::new(axum::body::boxed(AxumBody::from(authority.to_owned())));
+                if authority.starts_with("bad.") {
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                }
+                Ok(response)
+            })
+        }
+    }
+
+    fn upstreams() -> Vec<Uri> {
+        vec![
+            "http://a.internal".parse().unwrap(),
+            "http://b.internal".parse().unwrap(),
+            "http://bad.internal".parse().unwrap(),
+        ]
+    }
+
+    async fn send_to(pool: &UpstreamPool<Echo>, header: Option<&str>) -> (StatusCode, String) {
+        let mut request = HttpRequest::builder().uri("http://proxy.internal/foo?x=1");
+        if let Some(header) = header {
+            request = request.header("x-user", header);
+        }
+        let response = pool
+            .send(request.body(AxumBody::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_and_mark_down() {
+        let pool = UpstreamPool::new(Echo, upstreams()).max_failures(2);
+
+        let mut authorities = vec![];
+        for _ in 0..9 {
+            authorities.push(send_to(&pool, None).await.1);
+        }
+        assert_eq!(
+            &authorities[..3],
+            &["a.internal", "b.internal", "bad.internal"]
+        );
+        assert!(pool.endpoints()[2].is_down());
+        assert_eq!(
+            authorities.iter().filter(|x| *x == "bad.internal").count(),
+            2
+        );
+
+        pool.endpoints()[2].mark_up();
+        assert!(!pool.endpoints()[2].is_down());
+
+        // Checking does not end the down period, slow start begins on the next pick.
+        pool.endpoints()[2].mark_down(Duration::ZERO);
+        assert!(!pool.endpoints()[2].is_down());
+        assert!(pool.endpoints()[2].state().down_until.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_consistent_hash() {
+        let pool = UpstreamPool::new(Echo, upstreams()).strategy(Strategy::ConsistentHash(
+            HashKey::Header(HeaderName::from_static("x-user")),
+        ));
+        pool.endpoints()[2].mark_down(Duration::from_secs(60));
+
+        for user in ["alice", "bob", "carol"] {
+            let (status, authority) = send_to(&pool, Some(user)).await;
+            assert_eq!(status, StatusCode::OK);
+            for _ in 0..5 {
+                assert_eq!(send_to(&pool, Some(user)).await.1, authority);
+            }
+        }
+    }
+
+    #[test]
+    fn test_least_in_flight_and_slow_start() {
+        let pool = UpstreamPool::new(Echo, upstreams()).strategy(Strategy::LeastInFlight);
+        let _a = InFlightGuard::new(pool.endpoints()[0].to_owned());
+        let _bad = InFlightGuard::new(pool.endpoints()[2].to_owned());
+        assert_eq!(pool.pick(None).unwrap().authority(), "b.internal");
+
+        let pool = pool.slow_start(Duration::from_secs(60));
+        pool.endpoints()[1].mark_down(Duration::ZERO);
+        let weight = pool.endpoints()[1].weight(Instant::now(), pool.slow_start);
+        assert!(weight < 0.5);
+        assert_eq!(pool.pick(None).unwrap().authority(), "a.internal");
+
+        let pool = pool.strategy(Strategy::RandomTwoChoices);
+        pool.endpoints()[0].mark_down(Duration::from_secs(60));
+        pool.endpoints()[2].mark_down(Duration::from_secs(60));
+        assert_eq!(pool.pick(None).unwrap().authority(), "b.internal");
+        pool.endpoints()[1].mark_down(Duration::from_secs(60));
+        assert!(pool.pick(None).is_none());
+    }
+}